@@ -1,37 +1,154 @@
-use crate::haystack::Corpus;
+use crate::haystack::{self, Corpus, Location, STDIN_SOURCE};
 use serde_yaml::Mapping;
 use serde_yaml::Value;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// The kinds of containers in a pod spec, by the list they're declared in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContainerKind {
+    /// A regular container, in `containers`.
+    App,
+    Init,
+    Ephemeral,
+}
+
+impl ContainerKind {
+    const ALL: [ContainerKind; 3] = [
+        ContainerKind::App,
+        ContainerKind::Init,
+        ContainerKind::Ephemeral,
+    ];
+
+    /// The pod spec field listing containers of this kind.
+    pub fn field(self) -> &'static str {
+        match self {
+            ContainerKind::App => "containers",
+            ContainerKind::Init => "initContainers",
+            ContainerKind::Ephemeral => "ephemeralContainers",
+        }
+    }
+
+    fn from_field(field: &str) -> Option<ContainerKind> {
+        ContainerKind::ALL
+            .iter()
+            .cloned()
+            .find(|k| k.field() == field)
+    }
+}
+
+impl fmt::Display for ContainerKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ContainerKind::App => "container",
+            ContainerKind::Init => "init container",
+            ContainerKind::Ephemeral => "ephemeral container",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Container {
+    pub kind: ContainerKind,
+    pub name: String,
+}
 
 #[derive(Debug)]
-pub struct SecretRef(String);
+pub struct SecretRef {
+    pub name: String,
+    /// Marked `optional: true`, so pods start without the secret.
+    pub optional: bool,
+    pub location: Location,
+}
 
 #[derive(Debug)]
 pub struct SecretKeyRef {
-    name: String,
-    key: String,
+    pub name: String,
+    pub key: String,
+    /// Marked `optional: true`, so pods start without the secret or key.
+    pub optional: bool,
+    pub location: Location,
+}
+
+/// How a volume gets the contents of a secret.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VolumeSource {
+    /// A `secret` volume.
+    Secret,
+    /// A `secret` among the sources of a `projected` volume.
+    Projected,
+    /// A Secrets Store CSI driver volume, where the secret name is its `secretProviderClass`.
+    Csi,
+}
+
+impl VolumeSource {
+    /// The volume field the secret is declared in.
+    pub fn field(self) -> &'static str {
+        match self {
+            VolumeSource::Secret => "secret",
+            VolumeSource::Projected => "projected",
+            VolumeSource::Csi => "csi",
+        }
+    }
+}
+
+impl fmt::Display for VolumeSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            VolumeSource::Secret => "volume",
+            VolumeSource::Projected => "projected volume",
+            VolumeSource::Csi => "CSI volume",
+        })
+    }
 }
 
 #[derive(Debug)]
 pub struct VolumeSecret {
     volume_name: String,
     secret_name: String,
+    source: VolumeSource,
+    /// Keys selected with `items`, empty if the whole secret is used.
+    items: Vec<SecretItem>,
+    optional: bool,
+    /// Where the secret is referenced (ex. `volumes/0` or `volumes/1/projected/sources/2`).
+    location: Location,
+}
+
+impl VolumeSecret {
+    fn item_keys(&self) -> Vec<String> {
+        self.items.iter().map(|i| i.key.clone()).collect()
+    }
 }
 
+/// A key selected by an entry of `items`.
 #[derive(Debug)]
-pub struct VolumeUsage {
+struct SecretItem {
+    /// Position of the entry in `items`.
+    index: usize,
+    key: String,
+}
+
+/// A secret referenced by an entry of `volumes`, relative to the pod spec.
+struct PodVolumeSecret {
+    pointer: String,
     volume_name: String,
     secret_name: String,
-    mounted_in: String,
-    mount_paths: Vec<String>,
-    usages: Vec<String>,
+    source: VolumeSource,
+    items: Vec<SecretItem>,
+    optional: bool,
 }
 
-fn mapping_has_key(m: &Value, key: &str) -> bool {
-    match m {
-        Value::Mapping(m) => m.contains_key(&key.into()),
-        _ => false,
-    }
+#[derive(Debug)]
+pub struct VolumeUsage {
+    pub volume_name: String,
+    pub secret_name: String,
+    pub source: VolumeSource,
+    pub items: Vec<String>,
+    pub optional: bool,
+    pub mount_paths: Vec<String>,
+    pub usages: Vec<String>,
+    /// The container the volume is mounted in, or the volume itself if it isn't mounted.
+    pub location: Location,
 }
 
 fn mapping_has_value(m: &Value, key: &str, val: &str) -> bool {
@@ -50,129 +167,306 @@ fn value_contains(v: &Value, substr: &str) -> bool {
     }
 }
 
-fn filter_map_secret_ref(m: &Mapping) -> Option<SecretRef> {
+/// Whether a secret reference is marked `optional: true`.
+fn is_optional(secret: &Value) -> bool {
+    secret["optional"].as_bool().unwrap_or(false)
+}
+
+fn filter_map_secret_ref(m: &Mapping) -> Option<(String, bool)> {
     let k = "secretRef".into();
-    let secrets = m.get(&k)?.as_mapping()?;
-    let name = secrets.get(&"name".into())?.as_str()?.to_string();
-    Some(SecretRef(name))
+    let secret = m.get(&k)?;
+    let name = secret
+        .as_mapping()?
+        .get(&"name".into())?
+        .as_str()?
+        .to_string();
+    Some((name, is_optional(secret)))
 }
 
-fn filter_map_secret_key_ref(m: &Mapping) -> Option<SecretKeyRef> {
+fn filter_map_secret_key_ref(m: &Mapping) -> Option<(String, String, bool)> {
     let k = "secretKeyRef".into();
-    let secrets = m.get(&k)?.as_mapping()?;
+    let secret = m.get(&k)?;
+    let secrets = secret.as_mapping()?;
     let name = secrets.get(&"name".into())?.as_str()?.to_string();
     let key = secrets.get(&"key".into())?.as_str()?.to_string();
-    Some(SecretKeyRef { name, key })
+    Some((name, key, is_optional(secret)))
+}
+
+pub fn find_secret_refs(corpus: &Corpus) -> Vec<SecretRef> {
+    corpus
+        .find_mappings(filter_map_secret_ref)
+        .into_iter()
+        .map(|m| SecretRef {
+            name: m.value.0,
+            optional: m.value.1,
+            location: m.location,
+        })
+        .collect()
 }
 
-fn find_secret_refs(corpus: &Corpus) -> Vec<SecretRef> {
-    corpus.filter_map_mappings(&filter_map_secret_ref)
+pub fn find_secret_key_refs(corpus: &Corpus) -> Vec<SecretKeyRef> {
+    corpus
+        .find_mappings(filter_map_secret_key_ref)
+        .into_iter()
+        .map(|m| SecretKeyRef {
+            name: m.value.0,
+            key: m.value.1,
+            optional: m.value.2,
+            location: m.location,
+        })
+        .collect()
 }
 
-fn find_secret_key_refs(corpus: &Corpus) -> Vec<SecretKeyRef> {
-    corpus.filter_map_mappings(&filter_map_secret_key_ref)
+/// The keys selected by the `items` of a secret volume or projected secret.
+fn secret_items(secret: &Value) -> Vec<SecretItem> {
+    secret["items"]
+        .as_sequence()
+        .map(|items| {
+            items
+                .iter()
+                .enumerate()
+                .filter_map(|(index, i)| {
+                    Some(SecretItem {
+                        index,
+                        key: i["key"].as_str()?.to_string(),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
 }
 
-fn filter_map_vol_secrets(m: &Mapping) -> Option<Vec<VolumeSecret>> {
-    Some(
-        m.get(&"volumes".into())?
-            .as_sequence()?
-            .iter()
-            .filter(|v| v.is_mapping() && mapping_has_key(v, "secret"))
-            .map(|v| v.as_mapping().unwrap())
-            .map(|m| {
-                let name = m.get(&"name".into()).unwrap().as_str().unwrap();
-                let secret = m.get(&"secret".into()).unwrap().as_mapping().unwrap();
-                let secret_name = secret.get(&"secretName".into()).unwrap().as_str().unwrap();
-                VolumeSecret {
-                    volume_name: name.to_string(),
-                    secret_name: secret_name.to_string(),
-                }
+/// Secrets referenced by `secret`, `projected` and Secrets Store CSI volumes.
+fn filter_map_vol_secrets(m: &Mapping) -> Option<Vec<PodVolumeSecret>> {
+    let mut res = Vec::new();
+    let volumes = m.get(&"volumes".into())?.as_sequence()?;
+    for (i, v) in volumes.iter().enumerate() {
+        let volume_name = match v["name"].as_str() {
+            Some(n) => n.to_string(),
+            None => continue,
+        };
+        // `secret` is the secret's own fields, with `items` and `optional`
+        let mut push = |pointer: String, source, secret_name: &str, secret: &Value| {
+            res.push(PodVolumeSecret {
+                pointer,
+                volume_name: volume_name.clone(),
+                secret_name: secret_name.to_string(),
+                source,
+                items: secret_items(secret),
+                optional: is_optional(secret),
             })
-            .collect(),
-    )
+        };
+        if let Some(name) = v["secret"]["secretName"].as_str() {
+            let pointer = format!("volumes/{}", i);
+            push(pointer, VolumeSource::Secret, name, &v["secret"]);
+        }
+        if let Some(sources) = v["projected"]["sources"].as_sequence() {
+            for (j, source) in sources.iter().enumerate() {
+                if let Some(name) = source["secret"]["name"].as_str() {
+                    let pointer = format!("volumes/{}/projected/sources/{}", i, j);
+                    push(pointer, VolumeSource::Projected, name, &source["secret"]);
+                }
+            }
+        }
+        if let Some(name) = v["csi"]["volumeAttributes"]["secretProviderClass"].as_str() {
+            let pointer = format!("volumes/{}/csi", i);
+            push(pointer, VolumeSource::Csi, name, &Value::Null);
+        }
+    }
+    Some(res)
 }
 
 fn find_vol_secrets(corpus: &Corpus) -> Vec<VolumeSecret> {
     corpus
-        .filter_map_mappings(filter_map_vol_secrets)
+        .find_mappings(filter_map_vol_secrets)
         .into_iter()
-        .flat_map(|v| v)
+        .flat_map(|m| {
+            let location = m.location;
+            m.value.into_iter().map(move |v| VolumeSecret {
+                volume_name: v.volume_name,
+                secret_name: v.secret_name,
+                source: v.source,
+                items: v.items,
+                optional: v.optional,
+                location: Location {
+                    pointer: format!("{}/{}", location.pointer, v.pointer),
+                    ..location.clone()
+                },
+            })
+        })
         .collect()
 }
 
-fn filter_map_vol_usages(m: &Mapping, secret: &VolumeSecret) -> Option<Vec<VolumeUsage>> {
-    let containers = m.get(&"containers".into())?.as_sequence()?;
+/// Keys selected by the `items` of secret volumes and projected secrets, located at each item.
+pub fn find_vol_item_refs(corpus: &Corpus) -> Vec<SecretKeyRef> {
     let mut res = Vec::new();
-    for container_v in containers {
-        let container = container_v.as_mapping()?;
-        let name = container
-            .get(&"name".into())
-            .or_else(|| container.get(&"image".into()))?
-            .as_str()?;
-        let mount_paths: Vec<String> = container
-            .get(&"volumeMounts".into())?
-            .as_sequence()?
-            .iter()
-            .filter(|m| m.is_mapping() && mapping_has_value(m, "name", &secret.volume_name))
-            .map(|m| m.as_mapping().unwrap())
-            .map(|m| {
-                m.get(&"mountPath".into())
-                    .unwrap()
-                    .as_str()
-                    .unwrap()
-                    .to_string()
-            })
-            .collect();
-        if mount_paths.is_empty() {
-            continue;
+    for vol_secret in find_vol_secrets(corpus) {
+        let location = &vol_secret.location;
+        for item in &vol_secret.items {
+            res.push(SecretKeyRef {
+                name: vol_secret.secret_name.clone(),
+                key: item.key.clone(),
+                optional: vol_secret.optional,
+                location: Location {
+                    pointer: format!("{}/secret/items/{}", location.pointer, item.index),
+                    ..location.clone()
+                },
+            });
         }
+    }
+    res
+}
 
-        let usages = Corpus::filter_map_values_from(&container_v, |v| {
-            if mount_paths
-                .iter()
-                .any(|p| value_contains(v, &format!("{}/", p)))
-            {
-                Some(v.as_str()?.to_string())
-            } else {
-                None
+/// Describe where a reference was found, ex. ``Deployment api/web container `app` env[3]``,
+/// followed by the file and line of its document if it wasn't read from stdin.
+pub fn describe_location(corpus: &Corpus, location: &Location) -> String {
+    let document = corpus.document(location);
+    let segments: Vec<&str> = location.pointer.split('/').skip(1).collect();
+    let name_at = |end: usize| name_at(&document.value, &segments[..end]).unwrap_or("?");
+    let mut parts = vec![location.owner.to_string()];
+    let mut i = 0;
+    while i < segments.len() {
+        let index = segments.get(i + 1).and_then(|s| s.parse::<usize>().ok());
+        match (segments[i], index) {
+            (field, Some(_)) if ContainerKind::from_field(field).is_some() => {
+                let kind = ContainerKind::from_field(field).unwrap();
+                parts.push(format!("{} `{}`", kind, name_at(i + 2)))
             }
-        });
+            ("volumes", Some(_)) => parts.push(format!("volume `{}`", name_at(i + 2))),
+            (list @ "env", Some(n))
+            | (list @ "envFrom", Some(n))
+            | (list @ "sources", Some(n))
+            | (list @ "items", Some(n)) => parts.push(format!("{}[{}]", list, n)),
+            _ => {
+                i += 1;
+                continue;
+            }
+        }
+        i += 2;
+    }
+    if document.source != STDIN_SOURCE {
+        parts.push(format!("in {}:{}", document.source, document.line));
+    }
+    parts.join(" ")
+}
 
-        res.push(VolumeUsage {
-            volume_name: secret.volume_name.to_string(),
-            secret_name: secret.secret_name.to_string(),
-            mounted_in: name.to_string(),
-            mount_paths,
-            usages,
-        });
+fn filter_map_vol_usages(
+    m: &Mapping,
+    secret: &VolumeSecret,
+    pod_spec: &Location,
+) -> Option<Vec<VolumeUsage>> {
+    let mut res = Vec::new();
+    for kind in ContainerKind::ALL.iter() {
+        let containers = match m.get(&kind.field().into()).and_then(Value::as_sequence) {
+            Some(c) => c,
+            None => continue,
+        };
+        for (i, container_v) in containers.iter().enumerate() {
+            if let Some((mount_paths, usages)) = filter_map_vol_mounts(container_v, secret) {
+                res.push(VolumeUsage {
+                    volume_name: secret.volume_name.to_string(),
+                    secret_name: secret.secret_name.to_string(),
+                    source: secret.source,
+                    items: secret.item_keys(),
+                    optional: secret.optional,
+                    mount_paths,
+                    usages,
+                    location: Location {
+                        pointer: format!("{}/{}/{}", pod_spec.pointer, kind.field(), i),
+                        ..pod_spec.clone()
+                    },
+                });
+            }
+        }
     }
     Some(res)
 }
 
+/// Where `container_v` mounts the volume of `secret`, and the values referring to files under
+/// those mount paths.  `None` if the container doesn't mount it.
+fn filter_map_vol_mounts(
+    container_v: &Value,
+    secret: &VolumeSecret,
+) -> Option<(Vec<String>, Vec<String>)> {
+    let mount_paths: Vec<String> = container_v
+        .as_mapping()?
+        .get(&"volumeMounts".into())?
+        .as_sequence()?
+        .iter()
+        .filter(|m| m.is_mapping() && mapping_has_value(m, "name", &secret.volume_name))
+        .filter_map(|m| m["mountPath"].as_str())
+        .map(str::to_string)
+        .collect();
+    if mount_paths.is_empty() {
+        return None;
+    }
+
+    let usages = Corpus::filter_map_values_from(container_v, |v| {
+        if mount_paths
+            .iter()
+            .any(|p| value_contains(v, &format!("{}/", p)))
+        {
+            Some(v.as_str()?.to_string())
+        } else {
+            None
+        }
+    });
+    Some((mount_paths, usages))
+}
+
+/// The pointer to the pod spec declaring the volume at `pointer`.
+fn pod_spec_pointer(pointer: &str) -> &str {
+    pointer
+        .rfind("/volumes/")
+        .map(|i| &pointer[..i])
+        .unwrap_or("")
+}
+
 fn find_vol_usages(corpus: &Corpus, volume_secret: &VolumeSecret) -> Vec<VolumeUsage> {
-    corpus
-        .filter_map_mappings(|m| filter_map_vol_usages(m, &volume_secret))
-        .into_iter()
-        .flat_map(|v| v)
-        .collect()
+    // A volume can only be mounted by containers of the pod spec declaring it
+    let location = &volume_secret.location;
+    let pod_spec = Location {
+        pointer: pod_spec_pointer(&location.pointer).to_string(),
+        ..location.clone()
+    };
+    haystack::pointer(&corpus.document(location).value, &pod_spec.pointer)
+        .and_then(Value::as_mapping)
+        .and_then(|m| filter_map_vol_usages(m, volume_secret, &pod_spec))
+        .unwrap_or_default()
 }
 
 fn find_all_vol_usages(corpus: &Corpus, secrets: &[VolumeSecret]) -> Vec<VolumeUsage> {
     let mut res = Vec::new();
     for s in secrets {
-        res.extend(find_vol_usages(corpus, &s));
+        res.extend(find_vol_usages(corpus, s));
     }
     res
 }
 
-fn print_secret_refs<T: AsRef<str>>(secret_refs: &[T], map: &HashMap<String, Vec<String>>) {
+fn optional_label(optional: bool) -> &'static str {
+    if optional {
+        " (optional)"
+    } else {
+        ""
+    }
+}
+
+fn print_secret_refs(
+    corpus: &Corpus,
+    secret_refs: &[SecretRef],
+    map: &HashMap<String, Vec<SecretKeyRef>>,
+) {
     println!("REFERENCES TO WHOLE SECRETS");
     if secret_refs.is_empty() {
         println!("(None)");
     }
     for s in secret_refs {
-        println!("  Secret '{}'", s.as_ref());
+        println!(
+            "  Secret '{}'{} ({})",
+            s.name,
+            optional_label(s.optional),
+            describe_location(corpus, &s.location)
+        );
     }
     println!();
     println!("REFERENCES TO SECRET KEYS");
@@ -182,12 +476,17 @@ fn print_secret_refs<T: AsRef<str>>(secret_refs: &[T], map: &HashMap<String, Vec
     for (k, v) in map {
         println!("  Secret '{}':", k);
         for s in v {
-            println!("    {}", s);
+            println!(
+                "    {}{} ({})",
+                s.key,
+                optional_label(s.optional),
+                describe_location(corpus, &s.location)
+            );
         }
     }
 }
 
-fn print_vol_secrets(map: &HashMap<String, Vec<VolumeUsage>>) {
+fn print_vol_secrets(corpus: &Corpus, map: &HashMap<String, Vec<VolumeUsage>>) {
     println!("VOLUME SECRETS");
     if map.is_empty() {
         println!("(None)");
@@ -195,8 +494,19 @@ fn print_vol_secrets(map: &HashMap<String, Vec<VolumeUsage>>) {
     for (k, v) in map {
         println!("   Secret '{}':", k);
         for usage in v {
-            println!("    Container: {}", usage.mounted_in);
-            println!("    Volume Name: {}", usage.volume_name);
+            println!(
+                "    Container: {}",
+                describe_location(corpus, &usage.location)
+            );
+            println!(
+                "    Volume Name: {}{}",
+                usage.volume_name,
+                optional_label(usage.optional)
+            );
+            println!("    Volume Type: {}", usage.source.field());
+            if !usage.items.is_empty() {
+                println!("    Keys: {}", usage.items.join(", "));
+            }
             println!("    Mount Paths:");
             for p in usage.mount_paths.iter() {
                 println!("      {}", p);
@@ -209,25 +519,18 @@ fn print_vol_secrets(map: &HashMap<String, Vec<VolumeUsage>>) {
     }
 }
 
-pub fn grouped_secret_key_refs(corpus: &Corpus) -> HashMap<String, Vec<String>> {
-    let env_secrets = find_secret_key_refs(&corpus);
-    env_secrets
-        .into_iter()
-        .map(|s| (s.name, s.key))
-        .fold(HashMap::new(), |mut acc, (n, k)| {
-            let v = acc.entry(n).or_insert_with(Vec::new);
-            v.push(k);
-            acc
-        })
-}
-
-pub fn grouped_secret_refs(corpus: &Corpus) -> Vec<String> {
-    find_secret_refs(&corpus).into_iter().map(|r| r.0).collect()
+pub fn grouped_secret_key_refs(corpus: &Corpus) -> HashMap<String, Vec<SecretKeyRef>> {
+    let env_secrets = find_secret_key_refs(corpus);
+    env_secrets.into_iter().fold(HashMap::new(), |mut acc, s| {
+        let v = acc.entry(s.name.to_string()).or_insert_with(Vec::new);
+        v.push(s);
+        acc
+    })
 }
 
 pub fn grouped_vol_secrets(corpus: &Corpus) -> HashMap<String, Vec<VolumeUsage>> {
-    let vol_secrets = find_vol_secrets(&corpus);
-    let vol_usages = find_all_vol_usages(&corpus, &vol_secrets);
+    let vol_secrets = find_vol_secrets(corpus);
+    let vol_usages = find_all_vol_usages(corpus, &vol_secrets);
     vol_usages
         .into_iter()
         .map(|s| (s.secret_name.to_string(), s))
@@ -238,22 +541,620 @@ pub fn grouped_vol_secrets(corpus: &Corpus) -> HashMap<String, Vec<VolumeUsage>>
         })
 }
 
+/// Every use of a secret volume by a container.  Volumes that aren't mounted in any container
+/// are included once, located at the volume and without mount paths.
+pub fn volume_usages(corpus: &Corpus) -> Vec<VolumeUsage> {
+    let mut res = Vec::new();
+    for vol_secret in find_vol_secrets(corpus) {
+        let usages = find_vol_usages(corpus, &vol_secret);
+        if usages.is_empty() {
+            res.push(VolumeUsage {
+                items: vol_secret.item_keys(),
+                volume_name: vol_secret.volume_name,
+                secret_name: vol_secret.secret_name,
+                source: vol_secret.source,
+                optional: vol_secret.optional,
+                mount_paths: Vec::new(),
+                usages: Vec::new(),
+                location: vol_secret.location,
+            });
+        }
+        res.extend(usages);
+    }
+    res
+}
+
+/// The `name` of the object at the pointer made of `segments`.
+fn name_at<'a>(document: &'a Value, segments: &[&str]) -> Option<&'a str> {
+    let pointer: String = segments.iter().map(|s| format!("/{}", s)).collect();
+    haystack::pointer(document, &pointer)?["name"].as_str()
+}
+
+/// The container a location is in, if any.
+pub fn container(corpus: &Corpus, location: &Location) -> Option<Container> {
+    let segments: Vec<&str> = location.pointer.split('/').skip(1).collect();
+    let start = segments.windows(2).rposition(|w| {
+        ContainerKind::from_field(w[0]).is_some() && w[1].parse::<usize>().is_ok()
+    })?;
+    let name = name_at(&corpus.document(location).value, &segments[..start + 2])?;
+    Some(Container {
+        kind: ContainerKind::from_field(segments[start])?,
+        name: name.to_string(),
+    })
+}
+
+/// How a container consumes a secret, and whether it's marked `optional: true`.
+#[derive(Debug)]
+pub enum SecretUse {
+    /// All keys, through `envFrom`.
+    Whole { secret: String, optional: bool },
+    /// A single key, through `valueFrom.secretKeyRef`.
+    Key {
+        secret: String,
+        key: String,
+        optional: bool,
+    },
+    /// A secret volume, the keys it selects if not all of them, and where it is mounted.
+    Volume {
+        secret: String,
+        volume: String,
+        source: VolumeSource,
+        items: Vec<String>,
+        mount_paths: Vec<String>,
+        optional: bool,
+    },
+}
+
+impl SecretUse {
+    pub fn is_optional(&self) -> bool {
+        match self {
+            SecretUse::Whole { optional, .. }
+            | SecretUse::Key { optional, .. }
+            | SecretUse::Volume { optional, .. } => *optional,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ContainerSecrets {
+    /// `None` for secret volumes that aren't mounted in any container.
+    pub container: Option<Container>,
+    pub uses: Vec<SecretUse>,
+}
+
+/// The secrets consumed by the containers of a workload (Deployment, StatefulSet, CronJob, Pod...).
+#[derive(Debug)]
+pub struct WorkloadSecrets {
+    /// Where the workload was found, pointing at its whole document.
+    pub location: Location,
+    pub containers: Vec<ContainerSecrets>,
+}
+
+/// Group secret references by the workload and container consuming them, in the order
+/// workloads appear in the corpus.
+pub fn secrets_by_workload(corpus: &Corpus) -> Vec<WorkloadSecrets> {
+    let mut uses: Vec<(Location, SecretUse)> = Vec::new();
+    uses.extend(find_secret_refs(corpus).into_iter().map(|r| {
+        let secret_use = SecretUse::Whole {
+            secret: r.name,
+            optional: r.optional,
+        };
+        (r.location, secret_use)
+    }));
+    uses.extend(find_secret_key_refs(corpus).into_iter().map(|r| {
+        let secret_use = SecretUse::Key {
+            secret: r.name,
+            key: r.key,
+            optional: r.optional,
+        };
+        (r.location, secret_use)
+    }));
+    uses.extend(volume_usages(corpus).into_iter().map(|u| {
+        let secret_use = SecretUse::Volume {
+            secret: u.secret_name,
+            volume: u.volume_name,
+            source: u.source,
+            items: u.items,
+            mount_paths: u.mount_paths,
+            optional: u.optional,
+        };
+        (u.location, secret_use)
+    }));
+    uses.sort_by_key(|(location, _)| location.document);
+
+    let mut workloads: Vec<WorkloadSecrets> = Vec::new();
+    for (location, secret_use) in uses {
+        let container = container(corpus, &location);
+        if workloads
+            .last()
+            .map(|w| w.location.document != location.document)
+            .unwrap_or(true)
+        {
+            workloads.push(WorkloadSecrets {
+                location: Location {
+                    pointer: String::new(),
+                    ..location.clone()
+                },
+                containers: Vec::new(),
+            });
+        }
+        let containers = &mut workloads.last_mut().unwrap().containers;
+        match containers.iter_mut().find(|c| c.container == container) {
+            Some(c) => c.uses.push(secret_use),
+            None => containers.push(ContainerSecrets {
+                container,
+                uses: vec![secret_use],
+            }),
+        }
+    }
+    workloads
+}
+
+fn capitalize<T: fmt::Display>(value: T) -> String {
+    let value = value.to_string();
+    let mut chars = value.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => value,
+    }
+}
+
+fn print_workload_secrets(corpus: &Corpus, workloads: &[WorkloadSecrets]) {
+    if workloads.is_empty() {
+        println!("(None)");
+    }
+    for workload in workloads {
+        println!("{}", describe_location(corpus, &workload.location));
+        for container in &workload.containers {
+            match &container.container {
+                Some(Container { kind, name }) => println!("  {} '{}':", capitalize(kind), name),
+                None => println!("  Not mounted in any container:"),
+            }
+            for secret_use in &container.uses {
+                let mut line = match secret_use {
+                    SecretUse::Whole { secret, .. } => {
+                        format!("    Secret '{}' (all keys)", secret)
+                    }
+                    SecretUse::Key { secret, key, .. } => {
+                        format!("    Secret '{}' key '{}'", secret, key)
+                    }
+                    SecretUse::Volume {
+                        secret,
+                        volume,
+                        source,
+                        items,
+                        mount_paths,
+                        ..
+                    } => {
+                        let noun = match source {
+                            VolumeSource::Csi => "SecretProviderClass",
+                            _ => "Secret",
+                        };
+                        let mut line =
+                            format!("    {} '{}' as {} '{}'", noun, secret, source, volume);
+                        if !items.is_empty() {
+                            line.push_str(&format!(" (keys {})", items.join(", ")));
+                        }
+                        if !mount_paths.is_empty() {
+                            line.push_str(&format!(" at {}", mount_paths.join(", ")));
+                        }
+                        line
+                    }
+                };
+                if secret_use.is_optional() {
+                    line.push_str(" (optional)");
+                }
+                println!("{}", line);
+            }
+        }
+        println!();
+    }
+}
+
+/// The Secrets a SecretProviderClass syncs with `secretObjects`, keyed by the class name.
+fn filter_map_synced_secrets(m: &Mapping) -> Option<(String, Vec<String>)> {
+    if m.get(&"kind".into())?.as_str()? != "SecretProviderClass" {
+        return None;
+    }
+    let name = m.get(&"metadata".into())?["name"].as_str()?.to_string();
+    let secrets = m
+        .get(&"spec".into())
+        .and_then(|spec| spec["secretObjects"].as_sequence())
+        .map(|objects| {
+            objects
+                .iter()
+                .filter_map(|o| o["secretName"].as_str())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    Some((name, secrets))
+}
+
+/// Kubernetes Secrets used by volumes, and whether each use is optional.  A CSI volume names
+/// a SecretProviderClass rather than a Secret, so it only counts for the Secrets that class
+/// syncs, and only if the class is in `corpus`.
+fn vol_k8s_secret_names(corpus: &Corpus) -> Vec<(String, bool)> {
+    let synced: HashMap<String, Vec<String>> = corpus
+        .filter_map_mappings(filter_map_synced_secrets)
+        .into_iter()
+        .collect();
+    let mut res = Vec::new();
+    for v in find_vol_secrets(corpus) {
+        match v.source {
+            VolumeSource::Csi => {
+                for name in synced.get(&v.secret_name).into_iter().flatten() {
+                    res.push((name.clone(), v.optional));
+                }
+            }
+            _ => res.push((v.secret_name, v.optional)),
+        }
+    }
+    res
+}
+
+/// Names of the Kubernetes Secrets the chart uses.
 pub fn referenced_k8s_secret_names(corpus: &Corpus) -> HashSet<String> {
     let mut res = HashSet::new();
-    let secret_refs = find_secret_refs(&corpus);
-    let secret_key_refs = find_secret_key_refs(&corpus);
-    let vol_refs = find_vol_secrets(&corpus);
-    res.extend(secret_refs.into_iter().map(|r| r.0));
+    let secret_refs = find_secret_refs(corpus);
+    let secret_key_refs = find_secret_key_refs(corpus);
+    res.extend(secret_refs.into_iter().map(|r| r.name));
     res.extend(secret_key_refs.into_iter().map(|r| r.name));
-    res.extend(vol_refs.into_iter().map(|r| r.secret_name));
+    res.extend(
+        vol_k8s_secret_names(corpus)
+            .into_iter()
+            .map(|(name, _)| name),
+    );
     res
 }
 
+/// Secrets the chart only references as `optional: true`, which pods can start without.
+pub fn optional_k8s_secret_names(corpus: &Corpus) -> HashSet<String> {
+    let mut optional = HashSet::new();
+    let mut required = HashSet::new();
+    let refs = find_secret_refs(corpus)
+        .into_iter()
+        .map(|r| (r.name, r.optional))
+        .chain(
+            find_secret_key_refs(corpus)
+                .into_iter()
+                .map(|r| (r.name, r.optional)),
+        )
+        .chain(vol_k8s_secret_names(corpus));
+    for (name, is_optional) in refs {
+        if is_optional {
+            optional.insert(name);
+        } else {
+            required.insert(name);
+        }
+    }
+    optional.difference(&required).cloned().collect()
+}
+
+/// List secrets used by each workload and container.
+pub fn list_secrets_by_workload(corpus: &Corpus) {
+    print_workload_secrets(corpus, &secrets_by_workload(corpus));
+}
+
+/// List references to whole secrets, secret keys and secret volumes.
 pub fn list_secrets(corpus: &Corpus) {
-    let grouped_secret_refs = grouped_secret_refs(&corpus);
-    let grouped_secret_key_refs = grouped_secret_key_refs(&corpus);
-    let grouped_vols = grouped_vol_secrets(&corpus);
-    print_secret_refs(&grouped_secret_refs, &grouped_secret_key_refs);
+    let secret_refs = find_secret_refs(corpus);
+    let grouped_secret_key_refs = grouped_secret_key_refs(corpus);
+    let grouped_vols = grouped_vol_secrets(corpus);
+    print_secret_refs(corpus, &secret_refs, &grouped_secret_key_refs);
     println!();
-    print_vol_secrets(&grouped_vols);
+    print_vol_secrets(corpus, &grouped_vols);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        container, describe_location, find_secret_key_refs, grouped_vol_secrets,
+        optional_k8s_secret_names, referenced_k8s_secret_names, secrets_by_workload, volume_usages,
+        Container, ContainerKind, SecretUse, VolumeSource,
+    };
+    use crate::haystack::Corpus;
+
+    static CONTENTS: &str = r#"---
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  namespace: api
+  name: web
+spec:
+  template:
+    spec:
+      volumes:
+        - name: certs
+          secret:
+            secretName: web-tls
+      containers:
+        - name: app
+          env:
+            - name: LOG_LEVEL
+              value: debug
+            - name: DB_PASSWORD
+              valueFrom:
+                secretKeyRef:
+                  name: db
+                  key: password
+          volumeMounts:
+            - name: certs
+              mountPath: /etc/tls
+---
+apiVersion: v1
+kind: Pod
+metadata:
+  name: debug
+spec:
+  volumes:
+    - name: certs
+      emptyDir: {}
+  containers:
+    - name: shell
+      volumeMounts:
+        - name: certs
+          mountPath: /tmp/certs
+"#;
+
+    #[test]
+    fn describes_where_references_are() {
+        let corpus = Corpus::from_str(CONTENTS, "web.yaml").unwrap();
+        let key_refs = find_secret_key_refs(&corpus);
+        assert_eq!(key_refs.len(), 1);
+        assert_eq!(
+            describe_location(&corpus, &key_refs[0].location),
+            "Deployment api/web container `app` env[1] in web.yaml:1"
+        );
+
+        // The Pod's `certs` volume has the same name, but isn't the secret volume
+        let vols = grouped_vol_secrets(&corpus);
+        let usages = &vols["web-tls"];
+        assert_eq!(usages.len(), 1);
+        assert_eq!(
+            describe_location(&corpus, &usages[0].location),
+            "Deployment api/web container `app` in web.yaml:1"
+        );
+    }
+
+    #[test]
+    fn groups_secrets_by_workload() {
+        let corpus = Corpus::from_str(CONTENTS, "web.yaml").unwrap();
+        let workloads = secrets_by_workload(&corpus);
+        assert_eq!(workloads.len(), 1, "Pod without secrets was listed");
+        assert_eq!(
+            workloads[0].location.owner.to_string(),
+            "Deployment api/web"
+        );
+        let containers = &workloads[0].containers;
+        assert_eq!(containers.len(), 1);
+        assert_eq!(
+            containers[0].container.as_ref().map(|c| c.name.as_str()),
+            Some("app")
+        );
+        match &containers[0].uses[..] {
+            [SecretUse::Key { secret, key, .. }, SecretUse::Volume {
+                secret: vol_secret,
+                mount_paths,
+                ..
+            }] => {
+                assert_eq!((secret.as_str(), key.as_str()), ("db", "password"));
+                assert_eq!(vol_secret, "web-tls");
+                assert_eq!(mount_paths, &vec!["/etc/tls".to_string()]);
+            }
+            uses => panic!("Unexpected secret uses: {:?}", uses),
+        }
+    }
+
+    #[test]
+    fn finds_volumes_mounted_in_init_and_ephemeral_containers() {
+        let contents = r#"kind: Job
+metadata:
+  name: migrate
+spec:
+  template:
+    spec:
+      volumes:
+        - name: creds
+          secret:
+            secretName: db-admin
+      containers:
+        - name: app
+      initContainers:
+        - name: migrate
+          command: ["migrate", "--config", "/etc/creds/config.yaml"]
+          volumeMounts:
+            - name: creds
+              mountPath: /etc/creds
+      ephemeralContainers:
+        - name: debugger
+          volumeMounts:
+            - name: creds
+              mountPath: /creds
+"#;
+        let corpus = Corpus::from_str(contents, "job.yaml").unwrap();
+        let usages = volume_usages(&corpus);
+        let found: Vec<(Option<Container>, &[String])> = usages
+            .iter()
+            .map(|u| (container(&corpus, &u.location), &u.usages[..]))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    Some(Container {
+                        kind: ContainerKind::Init,
+                        name: "migrate".to_string()
+                    }),
+                    &["/etc/creds/config.yaml".to_string()][..]
+                ),
+                (
+                    Some(Container {
+                        kind: ContainerKind::Ephemeral,
+                        name: "debugger".to_string()
+                    }),
+                    &[][..]
+                ),
+            ]
+        );
+        assert_eq!(
+            describe_location(&corpus, &usages[0].location),
+            "Job migrate init container `migrate` in job.yaml:1"
+        );
+    }
+
+    #[test]
+    fn finds_projected_and_csi_secrets() {
+        let contents = r#"kind: Pod
+metadata:
+  name: api
+spec:
+  volumes:
+    - name: config
+      projected:
+        sources:
+          - configMap:
+              name: api-config
+          - secret:
+              name: api-keys
+              items:
+                - key: stripe
+                  path: keys/stripe
+          - secret:
+              name: api-tls
+    - name: vault
+      csi:
+        driver: secrets-store.csi.k8s.io
+        volumeAttributes:
+          secretProviderClass: api-vault
+    - name: broken
+      secret: {}
+  containers:
+    - name: api
+      volumeMounts:
+        - name: config
+          mountPath: /etc/api
+"#;
+        let corpus = Corpus::from_str(contents, "pod.yaml").unwrap();
+        let found: Vec<(String, VolumeSource, Vec<String>, Vec<String>)> = volume_usages(&corpus)
+            .into_iter()
+            .map(|u| (u.secret_name, u.source, u.items, u.mount_paths))
+            .collect();
+        let etc_api = vec!["/etc/api".to_string()];
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "api-keys".to_string(),
+                    VolumeSource::Projected,
+                    vec!["stripe".to_string()],
+                    etc_api.clone()
+                ),
+                (
+                    "api-tls".to_string(),
+                    VolumeSource::Projected,
+                    vec![],
+                    etc_api
+                ),
+                ("api-vault".to_string(), VolumeSource::Csi, vec![], vec![]),
+            ]
+        );
+        let mut names: Vec<String> = referenced_k8s_secret_names(&corpus).into_iter().collect();
+        names.sort();
+        assert_eq!(names, vec!["api-keys", "api-tls"]);
+        let usages = volume_usages(&corpus);
+        assert_eq!(
+            describe_location(
+                &corpus,
+                &grouped_vol_secrets(&corpus)["api-tls"][0].location
+            ),
+            "Pod api container `api` in pod.yaml:1"
+        );
+        assert_eq!(
+            describe_location(&corpus, &usages[2].location),
+            "Pod api volume `vault` in pod.yaml:1"
+        );
+    }
+
+    #[test]
+    fn resolves_csi_volumes_to_synced_secrets() {
+        let contents = r#"kind: SecretProviderClass
+metadata:
+  name: api-vault
+spec:
+  provider: vault
+  secretObjects:
+    - secretName: api-db
+      type: Opaque
+---
+kind: Pod
+metadata:
+  name: api
+spec:
+  volumes:
+    - name: vault
+      csi:
+        driver: secrets-store.csi.k8s.io
+        volumeAttributes:
+          secretProviderClass: api-vault
+    - name: other
+      csi:
+        driver: secrets-store.csi.k8s.io
+        volumeAttributes:
+          secretProviderClass: elsewhere
+  containers:
+    - name: api
+"#;
+        let corpus = Corpus::from_str(contents, "pod.yaml").unwrap();
+        let names: Vec<String> = referenced_k8s_secret_names(&corpus).into_iter().collect();
+        assert_eq!(names, vec!["api-db"]);
+        assert_eq!(volume_usages(&corpus).len(), 2);
+    }
+
+    #[test]
+    fn tracks_optional_references() {
+        let contents = r#"kind: Pod
+metadata:
+  name: api
+spec:
+  volumes:
+    - name: extra
+      secret:
+        secretName: extra-certs
+        optional: true
+  containers:
+    - name: api
+      envFrom:
+        - secretRef:
+            name: flags
+            optional: true
+      env:
+        - name: DSN
+          valueFrom:
+            secretKeyRef:
+              name: db
+              key: dsn
+              optional: true
+        - name: PASSWORD
+          valueFrom:
+            secretKeyRef:
+              name: db
+              key: password
+"#;
+        let corpus = Corpus::from_str(contents, "pod.yaml").unwrap();
+        let key_refs: Vec<(String, bool)> = find_secret_key_refs(&corpus)
+            .into_iter()
+            .map(|r| (r.key, r.optional))
+            .collect();
+        assert_eq!(
+            key_refs,
+            vec![("dsn".to_string(), true), ("password".to_string(), false)]
+        );
+        assert!(volume_usages(&corpus)[0].optional);
+
+        // `db` is also required by the PASSWORD variable
+        let mut optional: Vec<String> = optional_k8s_secret_names(&corpus).into_iter().collect();
+        optional.sort();
+        assert_eq!(optional, vec!["extra-certs", "flags"]);
+    }
 }
@@ -0,0 +1,348 @@
+//! # Doctor
+//!
+//! Diagnose why kube-vault can't talk to Vault: each check of the server, login and engines
+//! reports a status, and failed or suspicious ones come with a hint for fixing them.
+use crate::VaultPath;
+use failure::{bail, Error};
+use std::collections::BTreeSet;
+use vault::{VaultClient, VaultClientError};
+
+/// Token TTL below which `doctor` warns that a run may outlive the token.
+const MIN_TOKEN_TTL: i64 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+    /// What to do about a failed or warning check.
+    pub remediation: Option<String>,
+}
+
+impl Check {
+    fn ok<S: Into<String>>(name: &str, detail: S) -> Check {
+        Check {
+            name: name.to_string(),
+            status: Status::Ok,
+            detail: detail.into(),
+            remediation: None,
+        }
+    }
+
+    fn warn<S: Into<String>, R: Into<String>>(name: &str, detail: S, remediation: R) -> Check {
+        Check {
+            name: name.to_string(),
+            status: Status::Warn,
+            detail: detail.into(),
+            remediation: Some(remediation.into()),
+        }
+    }
+
+    fn fail<S: Into<String>, R: Into<String>>(name: &str, detail: S, remediation: R) -> Check {
+        Check {
+            name: name.to_string(),
+            status: Status::Fail,
+            detail: detail.into(),
+            remediation: Some(remediation.into()),
+        }
+    }
+}
+
+fn is_tls_error(e: &VaultClientError) -> bool {
+    let msg = e.to_string().to_lowercase();
+    ["certificate", "tls", "ssl", "handshake", "issuer"]
+        .iter()
+        .any(|s| msg.contains(s))
+}
+
+fn auth_remediation(auth_method: &str) -> &'static str {
+    match auth_method {
+        "github" => "Check that VAULT_GITHUB_TOKEN is a valid GitHub token with read:org scope, and that the GitHub auth method maps your organization or team to policies",
+        "approle" => "Check VAULT_ROLE_TOKEN and VAULT_SECRET_TOKEN (or VAULT_WRAPPED_SECRET_TOKEN); secret ids and wrapping tokens may be single-use or expired",
+        _ => "VAULT_TOKEN is invalid or expired, log in again with `vault login`",
+    }
+}
+
+/// Check the health of the vault server `client` points at, that it can log in, and that the
+/// engines of `vault_paths` are usable.  Checks that depend on an earlier failed check are skipped.
+pub fn diagnose(client: &mut VaultClient, vault_paths: &[VaultPath]) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let addrs = client
+        .vault_addrs()
+        .iter()
+        .map(|a| a.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let health = match client.health() {
+        Ok(h) => h,
+        Err(e) if is_tls_error(&e) => {
+            checks.push(Check::fail(
+                "TLS",
+                format!("Could not establish a trusted connection to {}: {}", addrs, e),
+                "Make sure the server certificate is issued for the host in VAULT_ADDR and that its CA is trusted by this system",
+            ));
+            return checks;
+        }
+        Err(e) => {
+            checks.push(Check::fail(
+                "Reachability",
+                format!("Could not reach {}: {}", addrs, e),
+                "Check that VAULT_ADDR is correct and that the server is reachable from this host (firewalls, proxies, VPN)",
+            ));
+            return checks;
+        }
+    };
+    let addr = client.vault_addr().clone();
+    checks.push(Check::ok(
+        "Reachability",
+        format!("Vault {} at {}", health.version, addr),
+    ));
+    if addr.scheme() == "https" {
+        checks.push(Check::ok("TLS", "Server certificate is trusted"));
+    } else {
+        checks.push(Check::warn(
+            "TLS",
+            "Connection is not encrypted",
+            "Use an https:// address in VAULT_ADDR",
+        ));
+    }
+
+    if !health.initialized {
+        checks.push(Check::fail(
+            "Seal",
+            "Vault is not initialized",
+            "Initialize the server with `vault operator init`",
+        ));
+        return checks;
+    }
+    if health.sealed {
+        let progress = match client.seal_status() {
+            Ok(s) => format!(" ({}/{} unseal keys provided)", s.progress, s.t),
+            Err(_) => String::new(),
+        };
+        checks.push(Check::fail(
+            "Seal",
+            format!("Vault is sealed{}", progress),
+            "Unseal the server with `vault operator unseal`, or check its auto-unseal configuration",
+        ));
+        return checks;
+    }
+    checks.push(Check::ok("Seal", "Vault is unsealed"));
+
+    if health.standby && !health.performance_standby {
+        checks.push(Check::warn(
+            "Standby",
+            "Node is a standby, requests will be forwarded to the active node",
+            "Point VAULT_ADDR at the active node or the cluster's load balancer",
+        ));
+    } else if health.performance_standby {
+        checks.push(Check::ok("Standby", "Node is a performance standby"));
+    } else {
+        checks.push(Check::ok("Standby", "Node is active"));
+    }
+
+    let auth_method = client.auth_method().to_string();
+    let info = match client.lookup_self() {
+        Ok(info) => info,
+        Err(e) => {
+            checks.push(Check::fail(
+                "Auth",
+                format!("Could not log in with {} auth: {}", auth_method, e),
+                auth_remediation(&auth_method),
+            ));
+            return checks;
+        }
+    };
+    checks.push(Check::ok(
+        "Auth",
+        format!(
+            "Logged in as {} via {} auth (policies: {})",
+            info.display_name,
+            auth_method,
+            info.policies.join(", ")
+        ),
+    ));
+
+    match &info.expire_time {
+        None => checks.push(Check::ok("Token TTL", "Token never expires")),
+        Some(_) if info.ttl < MIN_TOKEN_TTL => checks.push(Check::warn(
+            "Token TTL",
+            format!("Token expires in {}s", info.ttl),
+            "Renew the token with `vault token renew`, or log in again before long runs",
+        )),
+        Some(expires) => checks.push(Check::ok(
+            "Token TTL",
+            format!("{}s remaining (expires {})", info.ttl, expires),
+        )),
+    }
+
+    let engines: BTreeSet<&str> = vault_paths.iter().map(|p| p.engine.as_str()).collect();
+    for engine in engines {
+        let name = format!("Mount {}", engine);
+        let check = match client.mount_info(engine) {
+            Ok(mount) => match mount.kv_version() {
+                Some(2) => Check::ok(&name, "K/V version 2"),
+                Some(v) => Check::fail(
+                    &name,
+                    format!("K/V version {}, kube-vault requires version 2", v),
+                    format!(
+                        "Upgrade the engine with `vault kv enable-versioning {}`",
+                        engine
+                    ),
+                ),
+                None => Check::fail(
+                    &name,
+                    format!("Engine is of type {}, not K/V", mount.mount_type),
+                    "Use the name of a K/V version 2 engine in the vault path",
+                ),
+            },
+            Err(VaultClientError::NotFound(_)) => Check::fail(
+                &name,
+                "No secrets engine is mounted at this path",
+                "Check the engine part of the vault path (ex. engine-name:/apps/my-app)",
+            ),
+            Err(e) => Check::warn(
+                &name,
+                format!("Could not look up the engine: {}", e),
+                "Make sure the token has a policy granting access to the engine",
+            ),
+        };
+        checks.push(check);
+    }
+    checks
+}
+
+fn format_check(check: &Check) -> Vec<String> {
+    let label = match check.status {
+        Status::Ok => "[ OK ]",
+        Status::Warn => "[WARN]",
+        Status::Fail => "[FAIL]",
+    };
+    let mut lines = vec![format!("{} {}: {}", label, check.name, check.detail)];
+    if let Some(remediation) = &check.remediation {
+        lines.push(format!("       -> {}", remediation));
+    }
+    lines
+}
+
+/// Run all checks against the vault server of `client` and print the results.  A client that
+/// couldn't be created fails the configuration check.
+pub fn run(client: Result<VaultClient, Error>, vault_paths: &[VaultPath]) -> Result<(), Error> {
+    let checks = match client {
+        Ok(mut client) => diagnose(&mut client, vault_paths),
+        Err(e) => vec![Check::fail(
+            "Configuration",
+            e.to_string(),
+            "Set VAULT_ADDR and one of VAULT_TOKEN, VAULT_GITHUB_TOKEN or VAULT_ROLE_TOKEN with VAULT_SECRET_TOKEN or VAULT_WRAPPED_SECRET_TOKEN",
+        )],
+    };
+    for check in &checks {
+        for line in format_check(check) {
+            println!("{}", line);
+        }
+    }
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    if failed > 0 {
+        bail!("{} check(s) failed", failed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{diagnose, Status};
+    use crate::VaultPath;
+    use vault::cassette::Redaction;
+    use vault::test_util::FakeVault;
+    use vault::VaultClient;
+
+    fn statuses(checks: &[super::Check]) -> Vec<(&str, Status)> {
+        checks.iter().map(|c| (c.name.as_str(), c.status)).collect()
+    }
+
+    #[test]
+    fn diagnoses_server_and_mounts() {
+        let vault = FakeVault::start();
+        vault.mount_kv2("secret");
+        vault.mount_kv1("legacy");
+        vault.set_standby(true);
+        let paths = vec![
+            VaultPath {
+                engine: "secret".to_string(),
+                path: "apps".to_string(),
+            },
+            VaultPath {
+                engine: "legacy".to_string(),
+                path: "apps".to_string(),
+            },
+        ];
+        let checks = diagnose(&mut vault.client(), &paths);
+        assert_eq!(
+            statuses(&checks),
+            vec![
+                ("Reachability", Status::Ok),
+                ("TLS", Status::Warn),
+                ("Seal", Status::Ok),
+                ("Standby", Status::Warn),
+                ("Auth", Status::Ok),
+                ("Token TTL", Status::Ok),
+                ("Mount legacy", Status::Fail),
+                ("Mount secret", Status::Ok),
+            ]
+        );
+        assert!(checks[6]
+            .remediation
+            .as_ref()
+            .unwrap()
+            .contains("enable-versioning legacy"));
+
+        vault.set_sealed(true);
+        let checks = diagnose(&mut vault.client(), &paths);
+        assert_eq!(checks.last().unwrap().status, Status::Fail);
+        assert_eq!(
+            checks.last().unwrap().detail,
+            "Vault is sealed (0/3 unseal keys provided)"
+        );
+
+        vault.set_sealed(false);
+        let mut client = VaultClient::from_client_token(vault.addr().clone(), "bad-token");
+        let checks = diagnose(&mut client, &paths);
+        assert_eq!(checks.last().unwrap().name, "Auth");
+        assert_eq!(checks.last().unwrap().status, Status::Fail);
+    }
+
+    #[test]
+    fn reports_unreachable_server() {
+        let mut client = VaultClient::from_client_token("http://127.0.0.1:1".parse().unwrap(), "t");
+        let checks = diagnose(&mut client, &[]);
+        assert_eq!(statuses(&checks), vec![("Reachability", Status::Fail)]);
+    }
+
+    #[test]
+    fn diagnoses_replayed_cassette() {
+        let vault = FakeVault::start();
+        vault.mount_kv2("secret");
+        let paths = vec![VaultPath {
+            engine: "secret".to_string(),
+            path: "apps".to_string(),
+        }];
+        let dir = tempfile::tempdir().unwrap();
+        let cassette = dir.path().join("doctor.json");
+        let mut client = vault.client();
+        client.record_to(&cassette, Redaction::Redact).unwrap();
+        let recorded = diagnose(&mut client, &paths);
+
+        let mut client = VaultClient::replay(&cassette, None).unwrap();
+        let replayed = diagnose(&mut client, &paths);
+        assert_eq!(statuses(&replayed), statuses(&recorded));
+        assert!(replayed.iter().all(|c| c.status != Status::Fail));
+    }
+}
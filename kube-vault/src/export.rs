@@ -1,7 +1,8 @@
+use crate::source::SecretSource;
 use crate::SecretMapping;
 use crate::VaultPath;
 use failure::Error;
-use vault::VaultClient;
+use vault::SecretString;
 
 fn join_path(path: &VaultPath, key: &str) -> VaultPath {
     let mut path = path.clone();
@@ -14,21 +15,21 @@ fn join_path(path: &VaultPath, key: &str) -> VaultPath {
 }
 
 pub fn secrets_in_path(
-    client: &mut VaultClient,
+    source: &mut dyn SecretSource,
     path: &VaultPath,
 ) -> Result<Vec<SecretMapping>, Error> {
-    let keys = client.list_kv_keys(&path.engine, &path.path)?;
+    let keys = source.list(&path.engine, &path.path)?;
     Ok(keys
         .iter()
-        .map(|k| SecretMapping::new(k, join_path(&path, k)))
+        .map(|k| SecretMapping::new(k, join_path(path, k)))
         .collect())
 }
 
 pub fn single_secret(
-    client: &mut VaultClient,
+    source: &mut dyn SecretSource,
     path: &VaultPath,
     secret_name: &str,
-) -> Result<Option<String>, Error> {
-    let keys = client.get_kv_secret(&path.engine, &path.path)?;
+) -> Result<Option<SecretString>, Error> {
+    let keys = source.get(&path.engine, &path.path)?;
     Ok(keys.get(secret_name).cloned())
 }
@@ -1,73 +1,300 @@
-use crate::SecretMapping;
+use crate::source::{Lease, SecretSource, SourceError};
+use crate::{MappingKind, SecretMapping};
 use askama::Template;
-use base64;
+use chrono::{Duration, SecondsFormat, TimeZone, Utc};
 use failure::Error;
-use std::collections::HashMap;
-use vault::VaultClient;
+use std::collections::{HashMap, HashSet};
+use vault::api::PkiCertificate;
+use vault::secret::Secret;
+use vault::SecretString;
+use zeroize::Zeroize;
+
+pub const LEASE_ID_ANNOTATION: &str = "vault-lease-id";
+pub const LEASE_EXPIRY_ANNOTATION: &str = "vault-lease-expiry";
+pub const CERTIFICATE_SERIAL_ANNOTATION: &str = "vault-certificate-serial";
+pub const CERTIFICATE_EXPIRY_ANNOTATION: &str = "vault-certificate-expiry";
+
+mod filters {
+    use std::fmt::Display;
+
+    /// Quote a value as a YAML string.  JSON strings are valid YAML double-quoted scalars,
+    /// with any quotes, backslashes and line breaks in the value escaped.
+    pub fn quote<T: Display>(value: T) -> askama::Result<String> {
+        Ok(serde_json::Value::String(value.to_string()).to_string())
+    }
+}
 
 #[derive(Template)]
 #[template(path = "secret.yaml", escape = "none")]
 pub struct SecretsTemplate {
     secret_name: String,
     namespace: String,
-    vault_addr: String,
-    vault_engine: String,
-    vault_path: String,
-    encoded_data: HashMap<String, String>,
+    secret_type: String,
+    source_uri: String,
+    annotations: Vec<(String, String)>,
+    encoded_data: HashMap<String, Secret<String>>,
 }
 
 impl SecretsTemplate {
-    pub fn new(
-        vault_addr: &str,
+    pub fn new<T: AsRef<[u8]> + Zeroize>(
+        source_uri: &str,
         secret_name: &str,
         namespace: &str,
-        vault_engine: &str,
-        vault_path: &str,
-        data: HashMap<String, String>,
+        data: HashMap<String, Secret<T>>,
     ) -> SecretsTemplate {
         SecretsTemplate {
-            vault_addr: vault_addr.into(),
+            source_uri: source_uri.into(),
             secret_name: secret_name.into(),
             namespace: namespace.into(),
-            vault_engine: vault_engine.into(),
-            vault_path: vault_path.into(),
+            secret_type: "Opaque".into(),
+            annotations: Vec::new(),
             encoded_data: data
                 .into_iter()
-                .map(|(k, v)| (k, base64::encode(&v)))
+                .map(|(k, v)| (k, base64::encode(v.expose()).into()))
                 .collect(),
         }
     }
+
+    /// Set the kubernetes Secret `type`, which defaults to `Opaque`.
+    pub fn with_type(mut self, secret_type: &str) -> SecretsTemplate {
+        self.secret_type = secret_type.into();
+        self
+    }
+
+    /// Add an annotation to the Secret, in addition to `vault-uri`.
+    pub fn with_annotation(mut self, key: &str, value: &str) -> SecretsTemplate {
+        self.annotations.push((key.into(), value.into()));
+        self
+    }
+
+    /// Annotate the Secret with the `lease`'s id and expiry, counted from now.
+    pub fn with_lease(self, lease: &Lease) -> SecretsTemplate {
+        let expiry = Utc::now() + Duration::seconds(lease.duration);
+        self.with_annotation(LEASE_ID_ANNOTATION, &lease.id)
+            .with_annotation(
+                LEASE_EXPIRY_ANNOTATION,
+                &expiry.to_rfc3339_opts(SecondsFormat::Secs, true),
+            )
+    }
+}
+
+/// Keys of the Secrets built from issued certificates.
+pub const TLS_SECRET_KEYS: &[&str] = &["tls.crt", "tls.key", "ca.crt"];
+
+/// The `tls.crt`, `tls.key` and `ca.crt` entries of a `kubernetes.io/tls` Secret.
+/// `tls.crt` holds the certificate followed by its CA chain.
+///
+/// These are the keys of [`TLS_SECRET_KEYS`].
+fn tls_secret_data(cert: &PkiCertificate) -> HashMap<String, SecretString> {
+    let chain = match &cert.ca_chain {
+        Some(chain) if !chain.is_empty() => chain.join("\n"),
+        _ => cert.issuing_ca.clone(),
+    };
+    let mut data = HashMap::new();
+    data.insert(
+        "tls.crt".to_string(),
+        format!("{}\n{}\n", cert.certificate, chain).into(),
+    );
+    data.insert(
+        "tls.key".to_string(),
+        format!("{}\n", cert.private_key.expose()).into(),
+    );
+    data.insert(
+        "ca.crt".to_string(),
+        format!("{}\n", cert.issuing_ca).into(),
+    );
+    data
+}
+
+/// Leave out static mappings of secrets that are only referenced as `optional: true` and don't
+/// exist in the source, so pods start without them instead of generation failing.
+pub fn without_missing_optional(
+    mappings: Vec<SecretMapping>,
+    optional_names: &HashSet<String>,
+    source: &mut dyn SecretSource,
+) -> Vec<SecretMapping> {
+    let mut res = Vec::new();
+    for mapping in mappings {
+        if mapping.kind == MappingKind::Static && optional_names.contains(&mapping.kubernetes_name)
+        {
+            let (engine, path) = (&mapping.vault_path.engine, &mapping.vault_path.path);
+            let missing = match source.get(engine, path) {
+                Ok(data) => data.is_empty(),
+                Err(SourceError::NotFound(_)) => true,
+                // Left for create_secret_template to report
+                Err(_) => false,
+            };
+            if missing {
+                eprintln!(
+                    "Skipping optional secret '{}', not found at {}:{}",
+                    mapping.kubernetes_name, engine, path
+                );
+                continue;
+            }
+        }
+        res.push(mapping);
+    }
+    res
 }
 
 pub fn create_secret_template(
     mappings: &[SecretMapping],
     namespace: &str,
-    client: &mut VaultClient,
+    source: &mut dyn SecretSource,
 ) -> Result<(), Error> {
     for mapping in mappings {
-        let data = client.get_kv_secret(&mapping.vault_path.engine, &mapping.vault_path.path)?;
-        let template = SecretsTemplate::new(
-            client.vault_addr().as_str(),
-            &mapping.kubernetes_name,
-            &namespace,
-            &mapping.vault_path.engine,
-            &mapping.vault_path.path,
-            data,
-        );
+        let (engine, path) = (&mapping.vault_path.engine, &mapping.vault_path.path);
+        // Metadata is read after the secret, so that the vault-uri annotation points
+        // at the node that served it if the source fails over between addresses.
+        let template = match &mapping.kind {
+            MappingKind::Static => {
+                let data = source.get(engine, path)?;
+                let metadata = source.metadata(engine, path);
+                SecretsTemplate::new(&metadata.uri, &mapping.kubernetes_name, namespace, data)
+            }
+            MappingKind::Dynamic => {
+                let secret = source.get_leased(engine, path)?;
+                let metadata = source.metadata(engine, path);
+                let template = SecretsTemplate::new(
+                    &metadata.uri,
+                    &mapping.kubernetes_name,
+                    namespace,
+                    secret.data,
+                );
+                match &secret.lease {
+                    Some(lease) => template.with_lease(lease),
+                    None => template,
+                }
+            }
+            MappingKind::Certificate(request) => {
+                let cert = source.issue_certificate(engine, path, request)?;
+                let expiry = Utc.timestamp(cert.expiration, 0);
+                let metadata = source.metadata(engine, path);
+                SecretsTemplate::new(
+                    &metadata.uri,
+                    &mapping.kubernetes_name,
+                    namespace,
+                    tls_secret_data(&cert),
+                )
+                .with_type("kubernetes.io/tls")
+                .with_annotation(CERTIFICATE_SERIAL_ANNOTATION, &cert.serial_number)
+                .with_annotation(
+                    CERTIFICATE_EXPIRY_ANNOTATION,
+                    &expiry.to_rfc3339_opts(SecondsFormat::Secs, true),
+                )
+            }
+        };
         println!("{}", template.render().unwrap());
     }
     Ok(())
 }
 
-pub mod filters {
-    use askama::Result;
-    use std::fmt;
+#[cfg(test)]
+mod test {
+    use super::{
+        tls_secret_data, without_missing_optional, SecretsTemplate, LEASE_EXPIRY_ANNOTATION,
+        LEASE_ID_ANNOTATION,
+    };
+    use crate::source::SecretSource;
+    use crate::{MappingKind, SecretMapping, VaultPath};
+    use askama::Template;
+    use serde_yaml::Value;
+    use vault::api::CertificateRequest;
+    use vault::test_util::FakeVault;
+
+    #[test]
+    fn annotates_dynamic_secrets_with_lease() {
+        let vault = FakeVault::start();
+        vault.mount_database("database");
+        vault.add_database_role("database", "readonly", 3600);
+        let mut client = vault.client();
+
+        let secret = client.get_leased("database", "creds/readonly").unwrap();
+        let lease = secret.lease.unwrap();
+        assert_eq!(lease.duration, 3600);
+        let rendered = SecretsTemplate::new("uri", "db", "default", secret.data)
+            .with_lease(&lease)
+            .render()
+            .unwrap();
+
+        let doc: Value = serde_yaml::from_str(&rendered).unwrap();
+        let annotations = &doc["metadata"]["annotations"];
+        assert_eq!(annotations[LEASE_ID_ANNOTATION].as_str(), Some(&*lease.id));
+        assert!(annotations[LEASE_EXPIRY_ANNOTATION].as_str().is_some());
+        assert!(doc["data"]["username"].as_str().is_some());
+        assert!(doc["data"]["password"].as_str().is_some());
+    }
 
-    pub fn with_leading_slash(s: &dyn fmt::Display) -> Result<String> {
-        let mut s = s.to_string();
-        if !s.starts_with('/') {
-            s.insert(0, '/');
+    #[test]
+    fn escapes_annotations() {
+        let tricky = "a \"quoted\": value\n# with \\ more";
+        let rendered = SecretsTemplate::new::<Vec<u8>>(
+            "file:///tmp/a #b",
+            "db",
+            "default",
+            Default::default(),
+        )
+        .with_annotation("note", tricky)
+        .render()
+        .unwrap();
+
+        let doc: Value = serde_yaml::from_str(&rendered).unwrap();
+        let annotations = &doc["metadata"]["annotations"];
+        assert_eq!(annotations["vault-uri"].as_str(), Some("file:///tmp/a #b"));
+        assert_eq!(annotations["note"].as_str(), Some(tricky));
+    }
+
+    #[test]
+    fn builds_tls_secret_from_certificate() {
+        let vault = FakeVault::start();
+        vault.mount_pki("pki");
+        let mut client = vault.client();
+        let request = CertificateRequest {
+            common_name: "web.example.com".into(),
+            ..Default::default()
+        };
+        let cert = client
+            .issue_certificate("pki", "internal", &request)
+            .unwrap();
+
+        let data = tls_secret_data(&cert);
+        let tls_crt = data["tls.crt"].expose();
+        assert!(tls_crt.starts_with(&cert.certificate));
+        for ca in cert.ca_chain.as_ref().unwrap() {
+            assert!(tls_crt.contains(ca.as_str()));
         }
-        Ok(s)
+        assert_eq!(data["tls.key"].expose().trim(), cert.private_key.expose());
+        assert_eq!(data["ca.crt"].expose().trim(), cert.issuing_ca);
+    }
+
+    #[test]
+    fn skips_missing_optional_secrets() {
+        let vault = FakeVault::start();
+        vault.mount_kv2("secret");
+        vault.put_secret("secret", "apps/web/flags", &[("beta", "true")]);
+        let mapping = |name: &str| {
+            let path = VaultPath {
+                engine: "secret".into(),
+                path: format!("apps/web/{}", name),
+            };
+            SecretMapping::new(name, path)
+        };
+        let mappings = vec![
+            mapping("flags"),
+            mapping("extra"),
+            mapping("required"),
+            mapping("leased").with_kind(MappingKind::Dynamic),
+        ];
+        let optional = ["flags", "extra", "leased"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let mappings = without_missing_optional(mappings, &optional, &mut vault.client());
+        let names: Vec<&str> = mappings
+            .iter()
+            .map(|m| m.kubernetes_name.as_str())
+            .collect();
+        assert_eq!(names, vec!["flags", "required", "leased"]);
     }
 }
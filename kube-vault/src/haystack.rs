@@ -3,6 +3,12 @@
 //! Search through unknown YAML for specific data shapes using the visitor pattern.
 //!
 //! Currently only visits [`Mapping`][serde_yaml_mapping] types given a filter-map function.
+//! [`find_mappings`](struct.Corpus.html#method.find_mappings) also returns the
+//! [`Location`](struct.Location.html) of each match: its document, the object owning it and a
+//! JSON pointer to it.
+//!
+//! Input is parsed as a YAML stream, so `---` inside block scalars or strings (ex. PEM
+//! headers) doesn't split documents, and each document keeps the source and line it was read from.
 //!
 //! ## Examples
 //!
@@ -24,29 +30,128 @@
 //! ```
 //!
 //! [serde_yaml_mapping]: https://docs.rs/serde_yaml/0.8.9/serde_yaml/struct.Mapping.html
-use failure::Error;
+use failure::{bail, Error};
 use serde_yaml::Mapping;
-use serde_yaml::{self, Error as SerdeError, Value};
+use serde_yaml::{self, Value};
+use std::fmt;
 use std::io::prelude::*;
+use yaml_rust::parser::{Event, Parser};
+
+/// Name given to documents read from stdin.
+pub const STDIN_SOURCE: &str = "<stdin>";
 
+/// A single document of a YAML stream, and where it was read from.
 #[derive(Debug)]
+pub struct Document {
+    /// The input the document was read from, a file path or `<stdin>`.
+    pub source: String,
+    /// Line of the input the document starts on, starting from 1.
+    pub line: usize,
+    pub value: Value,
+}
+
+/// The top-level object of a document, which owns everything found in it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Owner {
+    pub kind: Option<String>,
+    pub namespace: Option<String>,
+    pub name: Option<String>,
+}
+
+impl Owner {
+    fn of(document: &Value) -> Owner {
+        let string = |v: &Value| v.as_str().map(str::to_string);
+        Owner {
+            kind: string(&document["kind"]),
+            namespace: string(&document["metadata"]["namespace"]),
+            name: string(&document["metadata"]["name"]),
+        }
+    }
+}
+
+impl fmt::Display for Owner {
+    /// Formats like `Deployment api/web`, leaving out whatever is missing.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match (&self.namespace, &self.name) {
+            (Some(ns), Some(name)) => Some(format!("{}/{}", ns, name)),
+            (None, Some(name)) => Some(name.to_string()),
+            _ => None,
+        };
+        match (&self.kind, name) {
+            (Some(kind), Some(name)) => write!(f, "{} {}", kind, name),
+            (Some(kind), None) => write!(f, "{}", kind),
+            (None, Some(name)) => write!(f, "{}", name),
+            (None, None) => write!(f, "(unnamed object)"),
+        }
+    }
+}
+
+/// Where in a Corpus a match was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    /// Index of the document in the Corpus.
+    pub document: usize,
+    pub owner: Owner,
+    /// JSON pointer to the match within its document (ex. `/spec/containers/0/env/3/valueFrom`).
+    pub pointer: String,
+}
+
+/// A value found by a visitor, and where it was found.
+#[derive(Debug, Clone)]
+pub struct Match<T> {
+    pub location: Location,
+    pub value: T,
+}
+
+#[derive(Debug, Default)]
 pub struct Corpus {
-    documents: Vec<Value>,
+    documents: Vec<Document>,
 }
 
 impl Corpus {
-    /// Create a Corpus from a `Reader`.  Supports multiple documents in stream that are separated with `---`.
+    /// Create a Corpus from a `Reader` of stdin.  See [`from_str`](#method.from_str).
     pub fn from_reader<R: Read>(mut reader: R) -> Result<Corpus, Error> {
         let mut buffer = String::new();
         reader.read_to_string(&mut buffer)?;
-        let results: Result<Vec<Value>, SerdeError> = buffer
-            .split("---")
-            .filter(|s| nonempty_document(s))
-            .map(|s: &str| serde_yaml::from_reader::<_, Value>(s.as_bytes()))
+        Corpus::from_str(&buffer, STDIN_SOURCE)
+    }
+
+    /// Create a Corpus from a YAML or JSON stream read from `source`.  Supports multiple YAML
+    /// documents separated with `---`, and concatenated JSON values (ex. `kubectl get -o json`
+    /// output).  Empty documents are skipped, and `List` objects (ex. `kind: List` or
+    /// `kind: SecretList`) are replaced with their items.
+    ///
+    /// Errors name the source, the document and the line of the input where parsing failed.
+    pub fn from_str(contents: &str, source: &str) -> Result<Corpus, Error> {
+        let values = if looks_like_json(contents) {
+            // Flow-style YAML also starts with `{` or `[`, so fall back to it before giving up.
+            parse_json_stream(contents, source)
+                .or_else(|e| parse_yaml_stream(contents, source).map_err(|_| e))?
+        } else {
+            parse_yaml_stream(contents, source)?
+        };
+        let documents = values
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .flat_map(|(line, value)| {
+                unwrap_lists(value).into_iter().map(move |value| Document {
+                    source: source.to_string(),
+                    line,
+                    value,
+                })
+            })
             .collect();
-        Ok(Corpus {
-            documents: results?,
-        })
+        Ok(Corpus { documents })
+    }
+
+    /// Add the documents of `other` after those already in the Corpus.
+    pub fn extend(&mut self, other: Corpus) {
+        self.documents.extend(other.documents);
+    }
+
+    /// The non-empty documents of the Corpus, in the order they were read.
+    pub fn documents(&self) -> &[Document] {
+        &self.documents
     }
 
     /// Visit all mappings in the Corpus, executing `filter_map` for each mapping.
@@ -54,16 +159,40 @@ impl Corpus {
     /// Returning `None` from the `filter_map` function will exclude the value from
     /// the resulting `Vec`.
     pub fn filter_map_mappings<FM, T>(&self, filter_map: FM) -> Vec<T>
+    where
+        FM: Fn(&Mapping) -> Option<T>,
+    {
+        self.find_mappings(filter_map)
+            .into_iter()
+            .map(|m| m.value)
+            .collect()
+    }
+
+    /// Like [`filter_map_mappings`](#method.filter_map_mappings), but also returns where
+    /// each value was found.
+    pub fn find_mappings<FM, T>(&self, filter_map: FM) -> Vec<Match<T>>
     where
         FM: Fn(&Mapping) -> Option<T>,
     {
         let mut res = Vec::new();
-        for doc in self.documents.iter() {
-            filter_mapping_visitor(doc, &mut res, &filter_map);
+        for (n, doc) in self.documents.iter().enumerate() {
+            let mut visitor = MappingVisitor {
+                document: n,
+                owner: Owner::of(&doc.value),
+                path: Vec::new(),
+                acc: &mut res,
+                filter_map: &filter_map,
+            };
+            visitor.visit(&doc.value);
         }
         res
     }
 
+    /// The document a match was found in.
+    pub fn document(&self, location: &Location) -> &Document {
+        &self.documents[location.document]
+    }
+
     /// Visit all mappings, starting from `val` as the root of the document and
     /// applying `filter_map`.
     pub fn filter_map_values_from<FM, T>(val: &Value, filter_map: FM) -> Vec<T>
@@ -76,20 +205,143 @@ impl Corpus {
     }
 }
 
-fn nonempty_document(s: &str) -> bool {
-    s.lines()
-        .map(str::trim)
-        .filter(|l| !l.starts_with('#'))
-        .filter(|&l| l != "")
-        .count()
-        > 0
+fn looks_like_json(contents: &str) -> bool {
+    contents.trim_start().starts_with(['{', '['])
+}
+
+/// Parse concatenated JSON values, returning each with the line it starts on.
+fn parse_json_stream(contents: &str, source: &str) -> Result<Vec<(usize, Value)>, Error> {
+    let mut values = Vec::new();
+    let mut stream = serde_json::Deserializer::from_str(contents).into_iter::<Value>();
+    loop {
+        let offset = stream.byte_offset();
+        let start = offset + (contents[offset..].len() - contents[offset..].trim_start().len());
+        match stream.next() {
+            Some(Ok(value)) => {
+                let line = contents[..start].matches('\n').count() + 1;
+                values.push((line, value));
+            }
+            Some(Err(e)) => bail!("{}: document {}: {}", source, values.len() + 1, e),
+            None => break,
+        }
+    }
+    Ok(values)
+}
+
+/// Parse a YAML stream, returning each document with the line it starts on.
+fn parse_yaml_stream(contents: &str, source: &str) -> Result<Vec<(usize, Value)>, Error> {
+    let starts = document_starts(contents, source)?;
+    let mut values = Vec::new();
+    for (n, (start, line)) in starts.iter().enumerate() {
+        let end = starts
+            .get(n + 1)
+            .map(|s| s.0)
+            .unwrap_or_else(|| contents.len());
+        match serde_yaml::from_str(&contents[*start..end]) {
+            Ok(value) => values.push((*line, value)),
+            Err(e) => bail!(
+                "{}: document {}: {}",
+                source,
+                n + 1,
+                stream_error(&e, *line)
+            ),
+        }
+    }
+    Ok(values)
+}
+
+/// Describe an error in a document starting on `line`, with its position in the whole stream
+/// rather than the document.
+fn stream_error(e: &serde_yaml::Error, line: usize) -> String {
+    let msg = e.to_string();
+    match (e.location(), msg.rfind(" at line ")) {
+        (Some(location), Some(at)) => format!(
+            "{} at line {} column {}",
+            &msg[..at],
+            location.line() + line - 1,
+            location.column()
+        ),
+        _ => msg,
+    }
+}
+
+/// Replace a `List` object (`kind: List`, or a typed list like `kind: SecretList`) with its
+/// items.  Items of typed lists may omit their `kind` and `apiVersion`, which are filled in
+/// from the list.
+fn unwrap_lists(value: Value) -> Vec<Value> {
+    let kind = value["kind"].as_str().unwrap_or_default().to_string();
+    let items = match value["items"].as_sequence() {
+        Some(items) if kind.ends_with("List") => items.clone(),
+        _ => return vec![value],
+    };
+    let item_kind = &kind[..kind.len() - "List".len()];
+    items
+        .into_iter()
+        .map(|mut item| {
+            if let Value::Mapping(m) = &mut item {
+                if !item_kind.is_empty() && !m.contains_key(&"kind".into()) {
+                    m.insert("kind".into(), item_kind.into());
+                }
+                if !m.contains_key(&"apiVersion".into()) {
+                    if let Some(api_version) = value.get("apiVersion") {
+                        m.insert("apiVersion".into(), api_version.clone());
+                    }
+                }
+            }
+            item
+        })
+        .flat_map(unwrap_lists)
+        .collect()
+}
+
+/// Find the byte offset of the start of the line each document of a YAML stream begins on,
+/// along with that line number.  Directives (ex. `%YAML 1.2`) right before a document's `---`
+/// are part of that document.
+fn document_starts(contents: &str, source: &str) -> Result<Vec<(usize, usize)>, Error> {
+    let mut parser = Parser::new(contents.chars());
+    let mut char_indices = contents.char_indices();
+    let mut chars_seen = 0;
+    let mut starts = Vec::new();
+    loop {
+        let (event, mark) = match parser.next() {
+            Ok(e) => e,
+            Err(e) => bail!("{}: document {}: {}", source, starts.len().max(1), e),
+        };
+        match event {
+            Event::DocumentStart => {
+                // Marks count characters, not bytes
+                let offset = char_indices
+                    .nth(mark.index() - chars_seen)
+                    .map(|(i, _)| i)
+                    .unwrap_or_else(|| contents.len());
+                chars_seen = mark.index() + 1;
+                let mut line_start = contents[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+                let mut line = mark.line();
+                while line_start > 0 {
+                    let prev = contents[..line_start - 1]
+                        .rfind('\n')
+                        .map(|i| i + 1)
+                        .unwrap_or(0);
+                    if !contents[prev..].starts_with('%') {
+                        break;
+                    }
+                    line_start = prev;
+                    line -= 1;
+                }
+                starts.push((line_start, line));
+            }
+            Event::StreamEnd => break,
+            _ => {}
+        }
+    }
+    Ok(starts)
 }
 
 fn filter_map_value_visitor<FM, T>(val: &Value, acc: &mut Vec<T>, filter_map: &FM)
 where
     FM: Fn(&Value) -> Option<T>,
 {
-    if let Some(v) = filter_map(&val) {
+    if let Some(v) = filter_map(val) {
         acc.push(v);
     }
     match val {
@@ -107,31 +359,85 @@ where
     }
 }
 
-fn filter_mapping_visitor<FM, T>(val: &Value, acc: &mut Vec<T>, filter_map: &FM)
+struct MappingVisitor<'a, FM, T> {
+    document: usize,
+    owner: Owner,
+    /// Keys and indexes leading to the value being visited.
+    path: Vec<String>,
+    acc: &'a mut Vec<Match<T>>,
+    filter_map: &'a FM,
+}
+
+impl<'a, FM, T> MappingVisitor<'a, FM, T>
 where
     FM: Fn(&Mapping) -> Option<T>,
 {
-    match val {
-        Value::Mapping(m) => {
-            if let Some(t) = filter_map(&m) {
-                acc.push(t);
-            }
-            for (_k, v) in m {
-                filter_mapping_visitor(v, acc, filter_map);
+    fn visit(&mut self, val: &Value) {
+        match val {
+            Value::Mapping(m) => {
+                if let Some(t) = (self.filter_map)(m) {
+                    self.acc.push(Match {
+                        location: Location {
+                            document: self.document,
+                            owner: self.owner.clone(),
+                            pointer: to_pointer(&self.path),
+                        },
+                        value: t,
+                    });
+                }
+                for (k, v) in m {
+                    self.path.push(key_segment(k));
+                    self.visit(v);
+                    self.path.pop();
+                }
             }
-        }
-        Value::Sequence(s) => {
-            for v in s {
-                filter_mapping_visitor(v, acc, filter_map);
+            Value::Sequence(s) => {
+                for (i, v) in s.iter().enumerate() {
+                    self.path.push(i.to_string());
+                    self.visit(v);
+                    self.path.pop();
+                }
             }
+            _ => {}
         }
-        _ => {}
     }
 }
 
+fn key_segment(key: &Value) -> String {
+    match key {
+        Value::String(s) => s.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Build a JSON pointer (RFC 6901) from path segments.
+fn to_pointer(path: &[String]) -> String {
+    path.iter()
+        .map(|s| format!("/{}", s.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+/// Look up a value within `val` by JSON pointer, like `serde_json::Value::pointer`.
+pub fn pointer<'a>(val: &'a Value, pointer: &str) -> Option<&'a Value> {
+    if pointer.is_empty() {
+        return Some(val);
+    }
+    pointer
+        .strip_prefix('/')?
+        .split('/')
+        .map(|s| s.replace("~1", "/").replace("~0", "~"))
+        .try_fold(val, |v, segment| match v {
+            Value::Mapping(m) => m.get(&segment.as_str().into()),
+            Value::Sequence(s) => s.get(segment.parse::<usize>().ok()?),
+            _ => None,
+        })
+}
+
 #[cfg(test)]
 mod test {
-    static CONTENTS: &'static str = r#"---
+    static CONTENTS: &str = r#"---
 a: "foo"
 nested: 
   - name: a
@@ -155,8 +461,8 @@ nested:
     fn can_read() {
         let corpus = get_test_corpus();
         assert_eq!(corpus.documents.len(), 2, "Didn't find two documents");
-        let doc1 = &corpus.documents[0];
-        let doc2 = &corpus.documents[1];
+        let doc1 = &corpus.documents[0].value;
+        let doc2 = &corpus.documents[1].value;
         assert!(doc1.is_mapping(), "First document was not a mapping");
         assert!(doc2.is_sequence(), "Second document was not a sequence");
         assert!(
@@ -170,6 +476,133 @@ nested:
         );
     }
 
+    static PEM: &str = r#"
+# leading comment
+apiVersion: v1
+kind: ConfigMap
+data:
+  ca.crt: |
+    -----BEGIN CERTIFICATE-----
+    MIIB
+    -----END CERTIFICATE-----
+  notes: "--- not a separator ---"
+---
+---
+kind: Secret
+"#;
+
+    #[test]
+    fn keeps_separators_inside_values() {
+        let corpus = Corpus::from_str(PEM, "chart.yaml").unwrap();
+        let docs = corpus.documents();
+        assert_eq!(docs.len(), 2, "empty document was not skipped");
+        assert_eq!(docs[0].source, "chart.yaml");
+        assert_eq!(docs[0].line, 3);
+        assert_eq!(docs[1].line, 12);
+        let data = &docs[0].value["data"];
+        assert!(data["ca.crt"]
+            .as_str()
+            .unwrap()
+            .starts_with("-----BEGIN CERTIFICATE-----\n"));
+        assert_eq!(data["notes"].as_str(), Some("--- not a separator ---"));
+    }
+
+    #[test]
+    fn errors_point_to_document_and_line() {
+        let contents = "a: 1\n---\nb: 2\n---\nc: [1\nd: 2\n";
+        let err = Corpus::from_str(contents, "bad.yaml")
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("bad.yaml: document 3: "), "{}", err);
+        assert!(err.contains("line 6"), "{}", err);
+    }
+
+    #[test]
+    fn keeps_directives_with_their_document() {
+        let contents = "a: 1\n...\n%YAML 1.2\n%TAG !k! tag:k8s.io,2019:\n---\nb: 2\n";
+        let corpus = Corpus::from_str(contents, "directives.yaml").unwrap();
+        let docs = corpus.documents();
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[1].value["b"].as_i64(), Some(2));
+        assert_eq!(docs[1].line, 3);
+    }
+
+    static KUBECTL_JSON: &str = r#"{
+    "apiVersion": "v1",
+    "kind": "List",
+    "items": [
+        {
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": { "name": "web" }
+        },
+        {
+            "apiVersion": "v1",
+            "kind": "SecretList",
+            "items": [{ "metadata": { "name": "db" } }]
+        }
+    ]
+}
+{"kind": "ConfigMap", "metadata": {"name": "settings"}}
+"#;
+
+    #[test]
+    fn reads_json_streams_and_unwraps_lists() {
+        let corpus = Corpus::from_str(KUBECTL_JSON, "dump.json").unwrap();
+        let docs = corpus.documents();
+        let kinds: Vec<&str> = docs
+            .iter()
+            .map(|d| d.value["kind"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds, vec!["Deployment", "Secret", "ConfigMap"]);
+        assert_eq!(docs[1].value["apiVersion"].as_str(), Some("v1"));
+        assert_eq!(docs[1].value["metadata"]["name"].as_str(), Some("db"));
+        assert_eq!((docs[0].line, docs[2].line), (1, 17));
+
+        let yaml_list = "kind: List\nitems:\n- kind: Secret\n- kind: Pod\n";
+        let corpus = Corpus::from_str(yaml_list, "list.yaml").unwrap();
+        assert_eq!(corpus.documents().len(), 2);
+
+        let err = Corpus::from_str("{\"kind\": \"Pod\"}\n{\"kind\":}", "bad.json")
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("bad.json: document 2: "), "{}", err);
+    }
+
+    #[test]
+    fn finds_mappings_with_locations() {
+        let contents = r#"
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  namespace: api
+  name: web
+spec:
+  containers:
+    - name: app
+      env:
+        - name: A
+        - name: B
+          valueFrom: { "a/b": { ref: x } }
+"#;
+        let corpus = Corpus::from_str(contents, "web.yaml").unwrap();
+        let found = corpus.find_mappings(|m| m.get(&"ref".into()).cloned());
+        assert_eq!(found.len(), 1);
+        let location = &found[0].location;
+        assert_eq!(location.document, 0);
+        assert_eq!(location.owner.to_string(), "Deployment api/web");
+        assert_eq!(location.pointer, "/spec/containers/0/env/1/valueFrom/a~1b");
+        let doc = &corpus.document(location).value;
+        assert_eq!(
+            super::pointer(doc, "/spec/containers/0/name").and_then(|v| v.as_str()),
+            Some("app")
+        );
+        assert_eq!(
+            super::pointer(doc, &location.pointer).unwrap()["ref"].as_str(),
+            Some("x")
+        );
+    }
+
     #[test]
     fn can_visit_mappings() {
         let corpus = get_test_corpus();
@@ -0,0 +1,251 @@
+//! Machine-readable output of `list`.
+//!
+//! Everything found in the manifests is reported with the workload and container using it and
+//! where it was found, so other tools can consume it without scraping the text output.  Fields
+//! are only ever added to this schema; removing or changing one bumps `schemaVersion`.
+use crate::chart::{self, Container, ContainerKind};
+use crate::haystack::{Corpus, Location, Owner};
+use failure::Error;
+
+const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Inventory {
+    pub schema_version: u32,
+    /// References to whole secrets, through `envFrom`.
+    pub secret_refs: Vec<SecretRefEntry>,
+    /// References to single keys, through `valueFrom.secretKeyRef`.
+    pub secret_key_refs: Vec<SecretKeyRefEntry>,
+    /// Secret volumes, once per container mounting them.
+    pub volume_usages: Vec<VolumeUsageEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Workload {
+    pub kind: Option<String>,
+    pub namespace: Option<String>,
+    pub name: Option<String>,
+}
+
+/// Where a finding was made.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Context {
+    pub workload: Workload,
+    /// `None` for secret volumes that aren't mounted in any container.
+    pub container: Option<String>,
+    /// `app`, `init` or `ephemeral`, `None` along with `container`.
+    pub container_type: Option<&'static str>,
+    /// The file the manifest was read from, or `<stdin>`.
+    pub source: String,
+    /// Line of the source the manifest starts on.
+    pub line: usize,
+    /// JSON pointer to the finding within the manifest.
+    pub pointer: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SecretRefEntry {
+    pub secret: String,
+    /// Marked `optional: true`, so pods start without the secret.
+    pub optional: bool,
+    #[serde(flatten)]
+    pub context: Context,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SecretKeyRefEntry {
+    pub secret: String,
+    pub key: String,
+    pub optional: bool,
+    #[serde(flatten)]
+    pub context: Context,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VolumeUsageEntry {
+    pub secret: String,
+    pub volume: String,
+    /// `secret`, `projected` or `csi`.  The secret of a `csi` volume is its `secretProviderClass`.
+    pub volume_type: &'static str,
+    /// Keys selected with `items`, empty if the whole secret is used.
+    pub keys: Vec<String>,
+    pub optional: bool,
+    pub mount_paths: Vec<String>,
+    /// Container `command` and `args` referring to files under the mount paths.
+    pub usages: Vec<String>,
+    #[serde(flatten)]
+    pub context: Context,
+}
+
+fn context(corpus: &Corpus, location: &Location) -> Context {
+    let document = corpus.document(location);
+    let Owner {
+        kind,
+        namespace,
+        name,
+    } = location.owner.clone();
+    let container = chart::container(corpus, location);
+    let container_type = container.as_ref().map(|c| match c.kind {
+        ContainerKind::App => "app",
+        ContainerKind::Init => "init",
+        ContainerKind::Ephemeral => "ephemeral",
+    });
+    Context {
+        workload: Workload {
+            kind,
+            namespace,
+            name,
+        },
+        container: container.map(|Container { name, .. }| name),
+        container_type,
+        source: document.source.clone(),
+        line: document.line,
+        pointer: location.pointer.clone(),
+    }
+}
+
+/// Collect every secret reference in `corpus`, in the order they appear.
+pub fn inventory(corpus: &Corpus) -> Inventory {
+    Inventory {
+        schema_version: SCHEMA_VERSION,
+        secret_refs: chart::find_secret_refs(corpus)
+            .into_iter()
+            .map(|r| SecretRefEntry {
+                context: context(corpus, &r.location),
+                secret: r.name,
+                optional: r.optional,
+            })
+            .collect(),
+        secret_key_refs: chart::find_secret_key_refs(corpus)
+            .into_iter()
+            .map(|r| SecretKeyRefEntry {
+                context: context(corpus, &r.location),
+                secret: r.name,
+                key: r.key,
+                optional: r.optional,
+            })
+            .collect(),
+        volume_usages: chart::volume_usages(corpus)
+            .into_iter()
+            .map(|u| VolumeUsageEntry {
+                context: context(corpus, &u.location),
+                secret: u.secret_name,
+                volume: u.volume_name,
+                volume_type: u.source.field(),
+                keys: u.items,
+                optional: u.optional,
+                mount_paths: u.mount_paths,
+                usages: u.usages,
+            })
+            .collect(),
+    }
+}
+
+pub fn to_json(corpus: &Corpus) -> Result<String, Error> {
+    Ok(serde_json::to_string_pretty(&inventory(corpus))?)
+}
+
+pub fn to_yaml(corpus: &Corpus) -> Result<String, Error> {
+    Ok(serde_yaml::to_string(&inventory(corpus))?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::to_json;
+    use crate::haystack::Corpus;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn serializes_findings_with_context() {
+        let contents = r#"kind: Deployment
+metadata:
+  name: web
+spec:
+  template:
+    spec:
+      containers:
+        - name: app
+          envFrom:
+            - secretRef:
+                name: common
+          env:
+            - name: PASSWORD
+              valueFrom:
+                secretKeyRef:
+                  name: db
+                  key: password
+          volumeMounts:
+            - name: certs
+              mountPath: /etc/certs
+      volumes:
+        - name: certs
+          secret:
+            secretName: web-tls
+        - name: unused
+          secret:
+            secretName: spare
+"#;
+        let corpus = Corpus::from_str(contents, "web.yaml").unwrap();
+        let value: Value = serde_json::from_str(&to_json(&corpus).unwrap()).unwrap();
+        let workload = json!({"kind": "Deployment", "namespace": null, "name": "web"});
+        assert_eq!(
+            value,
+            json!({
+                "schemaVersion": 1,
+                "secretRefs": [{
+                    "secret": "common",
+                    "optional": false,
+                    "workload": workload,
+                    "container": "app",
+                    "containerType": "app",
+                    "source": "web.yaml",
+                    "line": 1,
+                    "pointer": "/spec/template/spec/containers/0/envFrom/0",
+                }],
+                "secretKeyRefs": [{
+                    "secret": "db",
+                    "key": "password",
+                    "optional": false,
+                    "workload": workload,
+                    "container": "app",
+                    "containerType": "app",
+                    "source": "web.yaml",
+                    "line": 1,
+                    "pointer": "/spec/template/spec/containers/0/env/0/valueFrom",
+                }],
+                "volumeUsages": [{
+                    "secret": "web-tls",
+                    "volume": "certs",
+                    "volumeType": "secret",
+                    "keys": [],
+                    "mountPaths": ["/etc/certs"],
+                    "usages": [],
+                    "optional": false,
+                    "workload": workload,
+                    "container": "app",
+                    "containerType": "app",
+                    "source": "web.yaml",
+                    "line": 1,
+                    "pointer": "/spec/template/spec/containers/0",
+                }, {
+                    "secret": "spare",
+                    "volume": "unused",
+                    "volumeType": "secret",
+                    "keys": [],
+                    "mountPaths": [],
+                    "usages": [],
+                    "optional": false,
+                    "workload": workload,
+                    "container": null,
+                    "containerType": null,
+                    "source": "web.yaml",
+                    "line": 1,
+                    "pointer": "/spec/template/spec/volumes/1",
+                }],
+            })
+        );
+    }
+}
@@ -0,0 +1,139 @@
+//! # Leases
+//!
+//! Renew, revoke and look up the leases of dynamic secrets.  Lease ids are given directly, or
+//! read from the `vault-lease-id` annotations of Secrets previously emitted by `generate`.
+use crate::generate::LEASE_ID_ANNOTATION;
+use crate::haystack::Corpus;
+use crate::source::{SecretSource, SourceError};
+use failure::{bail, Error};
+use serde_yaml::Mapping;
+
+fn filter_map_lease_id(m: &Mapping) -> Option<String> {
+    if m.get(&"kind".into())?.as_str()? != "Secret" {
+        return None;
+    }
+    let annotations = m
+        .get(&"metadata".into())?
+        .as_mapping()?
+        .get(&"annotations".into())?
+        .as_mapping()?;
+    let id = annotations.get(&LEASE_ID_ANNOTATION.into())?.as_str()?;
+    Some(id.to_string())
+}
+
+/// Lease ids from the annotations of all kubernetes Secrets in `corpus`.
+pub fn lease_ids_from_corpus(corpus: &Corpus) -> Vec<String> {
+    corpus.filter_map_mappings(filter_map_lease_id)
+}
+
+/// Apply `f` to every lease, printing failures and bailing if any occurred.
+fn for_each_lease<F>(lease_ids: &[String], action: &str, mut f: F) -> Result<(), Error>
+where
+    F: FnMut(&str) -> Result<(), SourceError>,
+{
+    let mut failed = 0;
+    for id in lease_ids {
+        if let Err(e) = f(id) {
+            eprintln!("ERROR: Could not {} lease {}: {}", action, id, e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        bail!(
+            "Could not {} {} of {} leases",
+            action,
+            failed,
+            lease_ids.len()
+        );
+    }
+    Ok(())
+}
+
+pub fn renew(
+    lease_ids: &[String],
+    increment: Option<i64>,
+    source: &mut dyn SecretSource,
+) -> Result<(), Error> {
+    for_each_lease(lease_ids, "renew", |id| {
+        let lease = source.renew_lease(id, increment)?;
+        println!("Renewed {} for {}s", lease.id, lease.duration);
+        Ok(())
+    })
+}
+
+pub fn revoke(lease_ids: &[String], source: &mut dyn SecretSource) -> Result<(), Error> {
+    for_each_lease(lease_ids, "revoke", |id| {
+        source.revoke_lease(id)?;
+        println!("Revoked {}", id);
+        Ok(())
+    })
+}
+
+pub fn lookup(lease_ids: &[String], source: &mut dyn SecretSource) -> Result<(), Error> {
+    for_each_lease(lease_ids, "look up", |id| {
+        let info = source.lookup_lease(id)?;
+        println!("Lease '{}':", info.id);
+        println!("  Issued: {}", info.issue_time);
+        if let Some(renewed) = &info.last_renewal {
+            println!("  Last Renewal: {}", renewed);
+        }
+        if let Some(expires) = &info.expire_time {
+            println!("  Expires: {}", expires);
+        }
+        println!("  TTL: {}s", info.ttl);
+        println!("  Renewable: {}", info.renewable);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{lease_ids_from_corpus, renew, revoke};
+    use crate::haystack::Corpus;
+    use vault::test_util::FakeVault;
+
+    static CONTENTS: &str = r#"---
+apiVersion: v1
+kind: Secret
+metadata:
+  name: db
+  annotations:
+    vault-uri: https://vault.example.com/ui/vault/secrets/database/show/creds/readonly
+    vault-lease-id: "database/creds/readonly/abc"
+---
+apiVersion: v1
+kind: Secret
+metadata:
+  name: static
+  annotations:
+    vault-uri: https://vault.example.com/ui/vault/secrets/secret/show/apps/web
+"#;
+
+    #[test]
+    fn reads_lease_ids_from_annotations() {
+        let corpus = Corpus::from_reader(CONTENTS.as_bytes()).unwrap();
+        assert_eq!(
+            lease_ids_from_corpus(&corpus),
+            vec!["database/creds/readonly/abc"]
+        );
+    }
+
+    #[test]
+    fn renews_and_revokes_leases() {
+        let vault = FakeVault::start();
+        vault.mount_database("database");
+        vault.add_database_role("database", "readonly", 60);
+        let mut client = vault.client();
+        let lease_ids = vec![
+            client
+                .get_database_credentials("database", "readonly")
+                .unwrap()
+                .lease_id,
+        ];
+
+        renew(&lease_ids, Some(120), &mut client).unwrap();
+        revoke(&lease_ids, &mut client).unwrap();
+        assert!(vault.leases().is_empty());
+        assert!(renew(&lease_ids, None, &mut client).is_err());
+    }
+}
@@ -1,17 +1,27 @@
-use clap::{App, AppSettings, Arg, SubCommand};
-use dotenv;
+#[macro_use]
+extern crate serde_derive;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use failure::{bail, Error};
-use openssl_probe;
 use std::io;
-use vault::VaultClient;
 
 mod chart;
+mod doctor;
 mod export;
 mod generate;
 pub mod haystack;
+mod inventory;
+mod lease;
+mod manifests;
+mod preflight;
+mod source;
+mod transit;
 mod verify;
+mod whoami;
 
 use haystack::Corpus;
+use source::SecretSource;
+use vault::api::CertificateRequest;
 
 const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 
@@ -21,10 +31,29 @@ pub struct VaultPath {
     pub path: String,
 }
 
+/// How the secret behind a mapping is read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MappingKind {
+    /// A K/V secret.
+    Static,
+    /// A secret generated on read and issued with a lease (ex. `database:creds/my-role`).
+    Dynamic,
+    /// A `kubernetes.io/tls` secret issued from a PKI role (ex. `pki:internal`).
+    Certificate(CertificateRequest),
+}
+
+impl MappingKind {
+    /// Whether the secret is generated on read with a lease.
+    pub fn is_dynamic(&self) -> bool {
+        *self == MappingKind::Dynamic
+    }
+}
+
 #[derive(Debug)]
 pub struct SecretMapping {
     pub kubernetes_name: String,
     pub vault_path: VaultPath,
+    pub kind: MappingKind,
 }
 
 impl SecretMapping {
@@ -32,9 +61,15 @@ impl SecretMapping {
         SecretMapping {
             kubernetes_name: kubernetes_name.into(),
             vault_path,
+            kind: MappingKind::Static,
         }
     }
 
+    pub fn with_kind(mut self, kind: MappingKind) -> SecretMapping {
+        self.kind = kind;
+        self
+    }
+
     pub fn from_secret_names_and_vault_path<I>(
         secret_names: I,
         vault_path: VaultPath,
@@ -62,6 +97,31 @@ fn read_from_stdin() -> Result<Corpus, Error> {
     Corpus::from_reader(handle)
 }
 
+/// Read manifests from the `-f` files, directories and globs, or from stdin if none were given.
+fn read_corpus(matches: &ArgMatches) -> Result<Corpus, Error> {
+    match matches.values_of("filename") {
+        Some(patterns) => manifests::read(patterns),
+        None => read_from_stdin(),
+    }
+}
+
+fn filename_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("filename")
+        .short("f")
+        .long("filename")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("Manifest file, directory (searched recursively for .yaml, .yml and .json files) or glob to read instead of stdin, can be repeated")
+}
+
+fn open_source(matches: &ArgMatches) -> Result<Box<dyn SecretSource>, Error> {
+    source::from_spec(
+        matches.value_of("source").unwrap_or("vault"),
+        matches.value_of("record"),
+    )
+}
+
 fn validate_vault_path<T: AsRef<str>>(m: T) -> Result<(), String> {
     let m = m.as_ref();
     if !m.contains(':') {
@@ -73,13 +133,55 @@ fn validate_vault_path<T: AsRef<str>>(m: T) -> Result<(), String> {
     Ok(())
 }
 
+fn validate_seconds(s: String) -> Result<(), String> {
+    match s.parse::<i64>() {
+        Ok(n) if n > 0 => Ok(()),
+        _ => Err(format!("Invalid number of seconds: {}", s)),
+    }
+}
+
 fn validate_mapping(m: String) -> Result<(), String> {
     let split: Vec<&str> = m.splitn(2, '=').collect();
     if split.len() != 2 {
         return Err(format!("Invalid mapping (missing =): {}", m));
     }
     let (_kube_part, vault_part) = (split[0], split[1]);
-    validate_vault_path(&vault_part)
+    validate_vault_path(vault_part)
+}
+
+/// Parse a TLS mapping, `name=engine:role,cn=common.name[,san=alt.name...][,ttl=72h]`.
+fn parse_tls_mapping(m: &str) -> Result<SecretMapping, String> {
+    let split: Vec<&str> = m.splitn(2, '=').collect();
+    if split.len() != 2 {
+        return Err(format!("Invalid TLS mapping (missing =): {}", m));
+    }
+    let (kube_part, rest) = (split[0], split[1]);
+    let mut options = rest.split(',');
+    let vault_part = options.next().unwrap_or("");
+    validate_vault_path(vault_part)?;
+    let mut request = CertificateRequest::default();
+    for option in options {
+        match option.splitn(2, '=').collect::<Vec<&str>>().as_slice() {
+            ["cn", cn] => request.common_name = cn.to_string(),
+            ["san", san] => request.alt_names.push(san.to_string()),
+            ["ttl", ttl] => request.ttl = Some(ttl.to_string()),
+            _ => {
+                return Err(format!(
+                    "Invalid TLS mapping option '{}', expected cn=, san= or ttl=",
+                    option
+                ))
+            }
+        }
+    }
+    if request.common_name.is_empty() {
+        return Err(format!("Invalid TLS mapping (missing cn=): {}", m));
+    }
+    Ok(SecretMapping::new(kube_part, parse_vault_path(vault_part))
+        .with_kind(MappingKind::Certificate(request)))
+}
+
+fn validate_tls_mapping(m: String) -> Result<(), String> {
+    parse_tls_mapping(&m).map(|_| ())
 }
 
 fn parse_vault_path(s: &str) -> VaultPath {
@@ -103,7 +205,7 @@ fn parse_mappings<'a>(map_strs: impl Iterator<Item = &'a str>) -> Vec<SecretMapp
             (split[0], split[1])
         })
         .map(|(kube_part, vault_part)| {
-            let vault_path = parse_vault_path(&vault_part);
+            let vault_path = parse_vault_path(vault_part);
             SecretMapping::new(kube_part, vault_path)
         })
         .collect()
@@ -111,50 +213,116 @@ fn parse_mappings<'a>(map_strs: impl Iterator<Item = &'a str>) -> Vec<SecretMapp
 
 fn verify_secrets_in_path(
     vault_path: &VaultPath,
+    mappings: &[SecretMapping],
     corpus: &Corpus,
-    client: &mut VaultClient,
+    source: &mut dyn SecretSource,
 ) -> Result<(), Error> {
-    let messages = verify::verify_mapping(&corpus, &vault_path.engine, &vault_path.path, client);
-    match messages {
-        Ok(msgs) => {
-            msgs.iter().for_each(|msg| eprintln!("Verified {}", msg));
-        }
-        Err(msgs) => {
-            msgs.iter().for_each(|msg| eprintln!("ERROR: {}", msg));
-            bail!("Missing secrets in vault, exiting...");
-        }
-    }
-    Ok(())
+    let secrets: Vec<String> = mappings.iter().map(|m| m.kubernetes_name.clone()).collect();
+    let verification = verify::verify_mapping(
+        &secrets,
+        corpus,
+        &vault_path.engine,
+        &vault_path.path,
+        source,
+    );
+    report_verification(&verification)
 }
 
 fn verify_secrets(
     mappings: &[SecretMapping],
     corpus: &Corpus,
-    client: &mut VaultClient,
+    source: &mut dyn SecretSource,
 ) -> Result<(), Error> {
-    let messages = verify::verify_secrets_exist_in_vault(&mappings, &corpus, client);
-    match messages {
-        Ok(msgs) => {
-            msgs.iter().for_each(|msg| eprintln!("Verified {}", msg));
+    let verification = verify::verify_secrets_exist_in_vault(mappings, corpus, source);
+    report_verification(&verification)
+}
+
+/// Print the messages of `verification`, and bail if anything required is missing.
+fn report_verification(verification: &verify::Verification) -> Result<(), Error> {
+    if verification.errors.is_empty() {
+        for msg in &verification.verified {
+            eprintln!("Verified {}", msg);
         }
-        Err(msgs) => {
-            msgs.iter().for_each(|msg| eprintln!("ERROR: {}", msg));
-            bail!("Missing secrets in vault, exiting...");
+    }
+    for msg in &verification.warnings {
+        eprintln!("WARNING: {}", msg);
+    }
+    if !verification.errors.is_empty() {
+        for msg in &verification.errors {
+            eprintln!("ERROR: {}", msg);
         }
+        bail!("Missing secrets in vault, exiting...");
     }
     Ok(())
 }
 
+fn lease_id_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("LEASE_ID")
+        .multiple(true)
+        .help("Leases to act on.  If omitted, lease ids are read from the annotations of Secrets on stdin or in -f files")
+}
+
+/// Lease ids from the command line, or from Secrets on stdin if none were given.
+fn lease_ids(matches: &ArgMatches) -> Result<Vec<String>, Error> {
+    let lease_ids: Vec<String> = match matches.values_of("LEASE_ID") {
+        Some(ids) => ids.map(str::to_string).collect(),
+        None => lease::lease_ids_from_corpus(&read_corpus(matches)?),
+    };
+    if lease_ids.is_empty() {
+        bail!("No leases found");
+    }
+    Ok(lease_ids)
+}
+
 fn cli_main() -> Result<(), Error> {
     let app = App::new("kube-vault")
         .setting(AppSettings::SubcommandRequiredElseHelp)
         .author("Benn Sundsrud <benn.sundsrud@gmail.com>")
         .about("Manage k8s secrets with vault as the source-of-truth")
         .version(VERSION.unwrap_or("unknown"))
-        .subcommand(SubCommand::with_name("list").about("Lists secrets accessed by a chart"))
+        .arg(
+            Arg::with_name("source")
+                .long("source")
+                .global(true)
+                .takes_value(true)
+                .default_value("vault")
+                .validator(source::validate_spec)
+                .help("Where to read secrets from: 'vault', a directory of YAML/JSON files (ex. file:./secrets) or a recorded cassette (ex. replay:./vault.json)"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .global(true)
+                .takes_value(true)
+                .value_name("cassette")
+                .help("Record vault interactions to a cassette file for later replay"),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("Lists secrets accessed by a chart")
+                .arg(filename_arg())
+                .arg(
+                    Arg::with_name("group-by")
+                        .long("group-by")
+                        .takes_value(true)
+                        .possible_values(&["workload", "secret"])
+                        .default_value("workload")
+                        .help("List secrets per workload and container, or per kind of reference"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .possible_values(&["text", "json", "yaml"])
+                        .default_value("text")
+                        .help("Print a human readable listing, or every reference with its workload and container as JSON or YAML"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("verify")
                 .about("Verify secrets used by a chart exist in vault")
+                .arg(filename_arg())
                 .arg(
                     Arg::with_name("mapping")
                         .short("m")
@@ -179,6 +347,7 @@ fn cli_main() -> Result<(), Error> {
         .subcommand(
             SubCommand::with_name("generate")
                 .about("Create k8s secrets from vault")
+                .arg(filename_arg())
                 .arg(
                     Arg::with_name("mapping")
                         .short("m")
@@ -186,7 +355,7 @@ fn cli_main() -> Result<(), Error> {
                         .multiple(true)
                         .number_of_values(1)
                         .validator(validate_mapping)
-                        .required_unless("vault-path")
+                        .required_unless_one(&["vault-path", "transit", "dynamic", "tls"])
                         .conflicts_with("vault-path")
                         .help("Maps k8s secret name to vault path (ex. my-secrets=engine-name:/apps/my-app/secret)"),
                 )
@@ -195,10 +364,37 @@ fn cli_main() -> Result<(), Error> {
                         .short("p")
                         .takes_value(true)
                         .validator(validate_vault_path)
-                        .required_unless("mapping")
+                        .required_unless_one(&["mapping", "transit", "dynamic", "tls"])
                         .conflicts_with("mapping")
                         .help("Vault path to source secrets from (ex. engine-name:/apps/my-app)")
                 )
+                .arg(
+                    Arg::with_name("dynamic")
+                        .short("d")
+                        .long("dynamic")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .validator(validate_mapping)
+                        .help("Maps k8s secret name to a dynamic secret path, annotating the secret with its lease (ex. my-db=database:creds/my-role)"),
+                )
+                .arg(
+                    Arg::with_name("tls")
+                        .long("tls")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .validator(validate_tls_mapping)
+                        .help("Maps k8s secret name to a certificate issued by a PKI role (ex. my-tls=pki:my-role,cn=my-app.example.com,san=my-app.default.svc,ttl=72h)"),
+                )
+                .arg(
+                    Arg::with_name("transit")
+                        .long("transit")
+                        .takes_value(true)
+                        .value_name("engine:key")
+                        .validator(validate_vault_path)
+                        .help("Decrypt vault:v1:... values in Secrets from stdin or the -f files with this Transit key (ex. transit:my-app)")
+                )
                 .arg(
                     Arg::with_name("namespace")
                         .short("N")
@@ -241,83 +437,202 @@ fn cli_main() -> Result<(), Error> {
                         .takes_value(true)
                         .help("k8s namespace for generated secrets"),
                 ),
+        ).subcommand(
+            SubCommand::with_name("doctor")
+                .about("Check vault connectivity, seal state, authentication and engines, with hints for fixing problems")
+                .arg(
+                    Arg::with_name("vault-path")
+                        .short("p")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .validator(validate_vault_path)
+                        .help("Vault path whose engine should be checked (ex. engine-name:/apps/my-app)")
+                )
+        ).subcommand(
+            SubCommand::with_name("whoami")
+                .about("Show the identity, policies and TTL of the vault token in use")
+        ).subcommand(
+            SubCommand::with_name("lease")
+                .about("Manage leases of dynamic secrets")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("renew")
+                        .about("Renew leases")
+                        .arg(
+                            Arg::with_name("increment")
+                                .short("i")
+                                .long("increment")
+                                .takes_value(true)
+                                .validator(validate_seconds)
+                                .help("Requested TTL in seconds, defaults to the lease's original TTL")
+                        )
+                        .arg(lease_id_arg())
+                        .arg(filename_arg())
+                )
+                .subcommand(
+                    SubCommand::with_name("revoke")
+                        .about("Revoke leases, invalidating their secrets")
+                        .arg(lease_id_arg())
+                        .arg(filename_arg())
+                )
+                .subcommand(
+                    SubCommand::with_name("lookup")
+                        .about("Show the expiry and TTL of leases")
+                        .arg(lease_id_arg())
+                        .arg(filename_arg())
+                ),
         );
     let matches = app.get_matches();
 
-    if let Some(_subcommand) = matches.subcommand_matches("list") {
-        let corpus = read_from_stdin()?;
-        chart::list_secrets(&corpus);
+    if let Some(subcommand) = matches.subcommand_matches("list") {
+        let corpus = read_corpus(subcommand)?;
+        match (
+            subcommand.value_of("output"),
+            subcommand.value_of("group-by"),
+        ) {
+            (Some("json"), _) => println!("{}", inventory::to_json(&corpus)?),
+            (Some("yaml"), _) => print!("{}", inventory::to_yaml(&corpus)?),
+            (_, Some("secret")) => chart::list_secrets(&corpus),
+            _ => chart::list_secrets_by_workload(&corpus),
+        }
     } else if let Some(subcommand) = matches.subcommand_matches("verify") {
-        let corpus = read_from_stdin()?;
-        let client = VaultClient::from_env();
-        let mut client = match client {
-            Ok(c) => c,
-            Err(e) => bail!("Could not create vault client: {}", e),
-        };
+        let corpus = read_corpus(subcommand)?;
+        let mut source = open_source(subcommand)?;
         if subcommand.is_present("mapping") {
             let mappings = subcommand
                 .values_of("mapping")
                 .map(parse_mappings)
                 .unwrap_or_else(Vec::new);
-            verify_secrets(&mappings, &corpus, &mut client)?;
+            let checks = preflight::mapping_checks(&mappings);
+            preflight::check_capabilities(&checks, source.as_mut())?;
+            verify_secrets(&mappings, &corpus, source.as_mut())?;
         } else if subcommand.is_present("vault-path") {
             let vault_path = subcommand
                 .value_of("vault-path")
                 .map(parse_vault_path)
                 .unwrap();
-            verify_secrets_in_path(&vault_path, &corpus, &mut client)?;
+            let secrets = chart::referenced_k8s_secret_names(&corpus);
+            let mappings =
+                SecretMapping::from_secret_names_and_vault_path(secrets, vault_path.clone());
+            let mut checks = vec![preflight::list_check(&vault_path)];
+            checks.extend(preflight::mapping_checks(&mappings));
+            preflight::check_capabilities(&checks, source.as_mut())?;
+            verify_secrets_in_path(&vault_path, &mappings, &corpus, source.as_mut())?;
         }
     } else if let Some(subcommand) = matches.subcommand_matches("generate") {
-        let corpus = read_from_stdin()?;
+        let corpus = read_corpus(subcommand)?;
         let namespace = subcommand.value_of("namespace").unwrap(); // Is a required field
-        let client = VaultClient::from_env();
-        let mut client = match client {
-            Ok(c) => c,
-            Err(e) => bail!("Could not create vault client: {}", e),
-        };
-        if subcommand.is_present("mapping") {
-            let mappings = subcommand
-                .values_of("mapping")
-                .map(parse_mappings)
-                .unwrap_or_else(Vec::new);
-            verify_secrets(&mappings, &corpus, &mut client)?;
-            generate::create_secret_template(&mappings, &namespace, &mut client)?;
-        } else if subcommand.is_present("vault-path") {
-            let vault_path = subcommand
-                .value_of("vault-path")
-                .map(parse_vault_path)
-                .unwrap();
-            verify_secrets_in_path(&vault_path, &corpus, &mut client)?;
-            let secrets = chart::referenced_k8s_secret_names(&corpus);
-            let mappings = SecretMapping::from_secret_names_and_vault_path(secrets, vault_path);
-            generate::create_secret_template(&mappings, &namespace, &mut client)?;
+        let mut source = open_source(subcommand)?;
+        let transit_key = subcommand.value_of("transit").map(parse_vault_path);
+        let vault_path = subcommand.value_of("vault-path").map(parse_vault_path);
+        let dynamic_mappings: Vec<SecretMapping> = subcommand
+            .values_of("dynamic")
+            .map(parse_mappings)
+            .unwrap_or_else(Vec::new)
+            .into_iter()
+            .map(|m| m.with_kind(MappingKind::Dynamic))
+            .chain(
+                subcommand
+                    .values_of("tls")
+                    .into_iter()
+                    .flatten()
+                    .map(|m| parse_tls_mapping(m).unwrap()),
+            )
+            .collect();
+        let mut mappings = subcommand
+            .values_of("mapping")
+            .map(parse_mappings)
+            .unwrap_or_else(Vec::new);
+        if let Some(vault_path) = &vault_path {
+            let secrets = chart::referenced_k8s_secret_names(&corpus)
+                .into_iter()
+                .filter(|s| !dynamic_mappings.iter().any(|m| &m.kubernetes_name == s));
+            mappings = SecretMapping::from_secret_names_and_vault_path(secrets, vault_path.clone());
+        }
+
+        let mut checks = Vec::new();
+        checks.extend(transit_key.iter().map(preflight::transit_check));
+        checks.extend(vault_path.iter().map(preflight::list_check));
+        checks.extend(preflight::mapping_checks(&mappings));
+        checks.extend(preflight::mapping_checks(&dynamic_mappings));
+        preflight::check_capabilities(&checks, source.as_mut())?;
+
+        if let Some(transit_key) = &transit_key {
+            let secrets = transit::find_encrypted_secrets(&corpus);
+            let templates = transit::create_decrypted_secret_templates(
+                secrets,
+                namespace,
+                transit_key,
+                source.as_mut(),
+            )?;
+            for template in templates {
+                println!("{}", template);
+            }
+        }
+        let optional = chart::optional_k8s_secret_names(&corpus);
+        if let Some(vault_path) = &vault_path {
+            verify_secrets_in_path(vault_path, &mappings, &corpus, source.as_mut())?;
+            let mappings = generate::without_missing_optional(mappings, &optional, source.as_mut());
+            generate::create_secret_template(&mappings, namespace, source.as_mut())?;
+            generate::create_secret_template(&dynamic_mappings, namespace, source.as_mut())?;
+        } else if !mappings.is_empty() || !dynamic_mappings.is_empty() {
+            mappings.extend(dynamic_mappings);
+            verify_secrets(&mappings, &corpus, source.as_mut())?;
+            let mappings = generate::without_missing_optional(mappings, &optional, source.as_mut());
+            generate::create_secret_template(&mappings, namespace, source.as_mut())?;
         }
     } else if let Some(subcommand) = matches.subcommand_matches("export") {
         let namespace = subcommand.value_of("namespace").unwrap(); // Is a required field
-        let client = VaultClient::from_env();
-        let mut client = match client {
-            Ok(c) => c,
-            Err(e) => bail!("Could not create vault client: {}", e),
-        };
+        let mut source = open_source(subcommand)?;
         let vault_path = subcommand
             .value_of("vault-path")
             .map(parse_vault_path)
             .unwrap();
-        let secrets = export::secrets_in_path(&mut client, &vault_path)?;
-        generate::create_secret_template(&secrets, &namespace, &mut client)?;
+        let secrets = export::secrets_in_path(source.as_mut(), &vault_path)?;
+        generate::create_secret_template(&secrets, namespace, source.as_mut())?;
+    } else if let Some(subcommand) = matches.subcommand_matches("doctor") {
+        let spec = subcommand.value_of("source").unwrap_or("vault");
+        if spec.starts_with("file:") {
+            bail!("doctor only checks the vault and replay sources");
+        }
+        let vault_paths: Vec<VaultPath> = subcommand
+            .values_of("vault-path")
+            .into_iter()
+            .flatten()
+            .map(parse_vault_path)
+            .collect();
+        doctor::run(
+            source::vault_client(spec, subcommand.value_of("record")),
+            &vault_paths,
+        )?;
+    } else if let Some(subcommand) = matches.subcommand_matches("whoami") {
+        let mut source = open_source(subcommand)?;
+        whoami::print_token_info(source.as_mut())?;
+    } else if let Some(subcommand) = matches.subcommand_matches("lease") {
+        if let Some(renew) = subcommand.subcommand_matches("renew") {
+            let lease_ids = lease_ids(renew)?;
+            let increment = renew.value_of("increment").map(|i| i.parse().unwrap());
+            let mut source = open_source(renew)?;
+            lease::renew(&lease_ids, increment, source.as_mut())?;
+        } else if let Some(revoke) = subcommand.subcommand_matches("revoke") {
+            let lease_ids = lease_ids(revoke)?;
+            let mut source = open_source(revoke)?;
+            lease::revoke(&lease_ids, source.as_mut())?;
+        } else if let Some(lookup) = subcommand.subcommand_matches("lookup") {
+            let lease_ids = lease_ids(lookup)?;
+            let mut source = open_source(lookup)?;
+            lease::lookup(&lease_ids, source.as_mut())?;
+        }
     } else if let Some(subcommand) = matches.subcommand_matches("get") {
-        let client = VaultClient::from_env();
-        let mut client = match client {
-            Ok(c) => c,
-            Err(e) => bail!("Could not create vault client: {}", e),
-        };
+        let mut source = open_source(subcommand)?;
         let vault_path = subcommand
             .value_of("vault-path")
             .map(parse_vault_path)
             .unwrap();
         let secret_name = subcommand.value_of("SECRET").unwrap();
-        if let Some(secret) = export::single_secret(&mut client, &vault_path, &secret_name)? {
-            println!("{}", secret);
+        if let Some(secret) = export::single_secret(source.as_mut(), &vault_path, secret_name)? {
+            println!("{}", secret.expose());
         } else {
             bail!(
                 "Secret {}:{}/{} not found",
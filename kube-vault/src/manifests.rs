@@ -0,0 +1,167 @@
+use crate::haystack::Corpus;
+use failure::{bail, Error};
+use glob::MatchOptions;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Extensions of manifest files found when searching a directory.
+const MANIFEST_EXTENSIONS: &[&str] = &["yaml", "yml", "json"];
+
+/// Read manifests from files, directories and globs into a single `Corpus`.
+///
+/// Directories are searched recursively for `.yaml`, `.yml` and `.json` files, globs
+/// (including `**`) match the same files, and `-` reads stdin.  Every document keeps the path of the file it was read from.
+pub fn read<'a, I: IntoIterator<Item = &'a str>>(patterns: I) -> Result<Corpus, Error> {
+    let mut corpus = Corpus::default();
+    for path in expand(patterns)? {
+        if path == Path::new("-") {
+            let stdin = io::stdin();
+            corpus.extend(Corpus::from_reader(stdin.lock())?);
+            continue;
+        }
+        let contents = match fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(e) => bail!("{}: {}", path.display(), e),
+        };
+        corpus.extend(Corpus::from_str(&contents, &path.display().to_string())?);
+    }
+    Ok(corpus)
+}
+
+/// Expand `patterns` into the files they name, in order and without duplicates.
+fn expand<'a, I: IntoIterator<Item = &'a str>>(patterns: I) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
+    for pattern in patterns {
+        let matches = if pattern == "-" {
+            vec![PathBuf::from(pattern)]
+        } else if is_glob(pattern) {
+            let matches = glob(pattern)?;
+            if matches.is_empty() {
+                bail!("No files match {}", pattern);
+            }
+            matches
+        } else if Path::new(pattern).exists() {
+            vec![PathBuf::from(pattern)]
+        } else {
+            bail!("{}: No such file or directory", pattern);
+        };
+        for path in matches {
+            if path.is_dir() {
+                walk(&path, &mut files)?;
+            } else if !files.contains(&path) {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Add manifest files under `dir` to `files`, recursively and in name order.
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for path in sorted_entries(dir)? {
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else if is_manifest(&path) && !files.contains(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn sorted_entries(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) => bail!("{}: {}", dir.display(), e),
+    };
+    let mut paths = entries
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<Vec<PathBuf>, io::Error>>()?;
+    paths.sort();
+    Ok(paths)
+}
+
+fn is_manifest(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| MANIFEST_EXTENSIONS.contains(&e))
+        .unwrap_or(false)
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Expand the glob `pattern` into the directories and manifest files it matches.  Other
+/// files are skipped, as they are when searching a directory.
+fn glob(pattern: &str) -> Result<Vec<PathBuf>, Error> {
+    let options = MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: true,
+        require_literal_leading_dot: true,
+    };
+    let paths = match glob::glob_with(pattern, options) {
+        Ok(p) => p,
+        Err(e) => bail!("{}: {}", pattern, e),
+    };
+    let mut matches = Vec::new();
+    for path in paths {
+        let path = path?;
+        if path.is_dir() || is_manifest(&path) {
+            matches.push(path);
+        }
+    }
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod test {
+    use super::read;
+    use std::fs;
+
+    #[test]
+    fn reads_files_directories_and_globs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("charts/web/templates")).unwrap();
+        fs::create_dir_all(root.join("charts/api")).unwrap();
+        fs::write(root.join("charts/web/templates/a.yaml"), "kind: A\n").unwrap();
+        fs::write(
+            root.join("charts/web/templates/b.json"),
+            "{\"kind\": \"B\"}",
+        )
+        .unwrap();
+        fs::write(root.join("charts/web/README.md"), "# not a manifest\n").unwrap();
+        fs::write(root.join("charts/api/c.yml"), "kind: C\n---\nkind: D\n").unwrap();
+        let root = root.display().to_string();
+
+        let kinds = |corpus: &crate::haystack::Corpus| -> Vec<String> {
+            corpus
+                .documents()
+                .iter()
+                .map(|d| d.value["kind"].as_str().unwrap().to_string())
+                .collect()
+        };
+        let corpus = read(vec![format!("{}/charts", root).as_str()]).unwrap();
+        assert_eq!(kinds(&corpus), vec!["C", "D", "A", "B"]);
+        assert_eq!(
+            corpus.documents()[1].source,
+            format!("{}/charts/api/c.yml", root)
+        );
+        assert_eq!(corpus.documents()[1].line, 2);
+
+        let corpus = read(vec![
+            format!("{}/charts/**/*.yaml", root).as_str(),
+            format!("{}/charts/web/templates/a.yaml", root).as_str(),
+        ])
+        .unwrap();
+        assert_eq!(kinds(&corpus), vec!["A"]);
+
+        let corpus = read(vec![format!("{}/charts/web/*", root).as_str()]).unwrap();
+        assert_eq!(kinds(&corpus), vec!["A", "B"]);
+
+        let err = read(vec![format!("{}/charts/*.txt", root).as_str()]).unwrap_err();
+        assert!(err.to_string().starts_with("No files match"));
+        assert!(read(vec![format!("{}/missing.yaml", root).as_str()]).is_err());
+    }
+}
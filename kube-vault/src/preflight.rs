@@ -0,0 +1,205 @@
+//! # Preflight
+//!
+//! Check the token's capabilities on every path a run will touch, using `sys/capabilities-self`,
+//! so that access problems are reported per mapping before any secret is read.
+//!
+//! Paths are derived assuming K/V version 2 engines, as used by [`SecretSource::get`].
+use crate::source::SecretSource;
+use crate::{MappingKind, SecretMapping, VaultPath};
+use failure::{bail, Error};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Read,
+    List,
+    Update,
+}
+
+impl Operation {
+    fn capability(self) -> &'static str {
+        match self {
+            Operation::Read => "read",
+            Operation::List => "list",
+            Operation::Update => "update",
+        }
+    }
+}
+
+/// An operation on a logical path, and what it's needed for.
+#[derive(Debug)]
+pub struct PathCheck {
+    description: String,
+    api_path: String,
+    operation: Operation,
+}
+
+impl PathCheck {
+    fn new<S: Into<String>>(description: S, api_path: String, operation: Operation) -> PathCheck {
+        PathCheck {
+            description: description.into(),
+            api_path,
+            operation,
+        }
+    }
+
+    fn is_allowed(&self, capabilities: &[String]) -> bool {
+        capabilities
+            .iter()
+            .any(|c| c == "root" || c == self.operation.capability())
+    }
+}
+
+fn api_path(engine: &str, infix: &str, path: &str) -> String {
+    let path = path.trim_start_matches('/');
+    if infix.is_empty() {
+        format!("{}/{}", engine, path)
+    } else {
+        format!("{}/{}/{}", engine, infix, path)
+    }
+}
+
+/// The operations needed to read the secret behind each mapping.
+pub fn mapping_checks(mappings: &[SecretMapping]) -> Vec<PathCheck> {
+    mappings
+        .iter()
+        .map(|m| {
+            let (engine, path) = (&m.vault_path.engine, &m.vault_path.path);
+            let description = format!("secret '{}' ({}:{})", m.kubernetes_name, engine, path);
+            match m.kind {
+                MappingKind::Static => {
+                    PathCheck::new(description, api_path(engine, "data", path), Operation::Read)
+                }
+                MappingKind::Dynamic => {
+                    PathCheck::new(description, api_path(engine, "", path), Operation::Read)
+                }
+                MappingKind::Certificate(_) => PathCheck::new(
+                    description,
+                    api_path(engine, "issue", path),
+                    Operation::Update,
+                ),
+            }
+        })
+        .collect()
+}
+
+/// The operation needed to list the secrets in `vault_path`.
+pub fn list_check(vault_path: &VaultPath) -> PathCheck {
+    PathCheck::new(
+        format!("{}:{}", vault_path.engine, vault_path.path),
+        api_path(&vault_path.engine, "metadata", &vault_path.path),
+        Operation::List,
+    )
+}
+
+/// The operation needed to decrypt with the Transit key `transit_key`.
+pub fn transit_check(transit_key: &VaultPath) -> PathCheck {
+    PathCheck::new(
+        format!("Transit key {}:{}", transit_key.engine, transit_key.path),
+        api_path(&transit_key.engine, "decrypt", &transit_key.path),
+        Operation::Update,
+    )
+}
+
+/// Check `checks` against the token's capabilities, returning a message for each.
+fn evaluate(
+    checks: &[PathCheck],
+    source: &mut dyn SecretSource,
+) -> Result<Result<Vec<String>, Vec<String>>, Error> {
+    let paths: Vec<String> = checks.iter().map(|c| c.api_path.clone()).collect();
+    let capabilities = source.capabilities(&paths)?;
+    let mut allowed = Vec::new();
+    let mut denied = Vec::new();
+    for check in checks {
+        let caps = capabilities
+            .get(&check.api_path)
+            .map(Vec::as_slice)
+            .unwrap_or(&[]);
+        if check.is_allowed(caps) {
+            allowed.push(format!(
+                "Token can {} {} for {}",
+                check.operation.capability(),
+                check.api_path,
+                check.description
+            ));
+        } else {
+            denied.push(format!(
+                "Token cannot {} {} for {} (capabilities: {})",
+                check.operation.capability(),
+                check.api_path,
+                check.description,
+                caps.join(", ")
+            ));
+        }
+    }
+    if denied.is_empty() {
+        Ok(Ok(allowed))
+    } else {
+        Ok(Err(denied))
+    }
+}
+
+/// Report whether the token can perform each of `checks`, and bail if any are denied.
+///
+/// Sources without tokens (ex. `file:`) skip the preflight, and a failure to look up
+/// capabilities is only a warning.
+pub fn check_capabilities(
+    checks: &[PathCheck],
+    source: &mut dyn SecretSource,
+) -> Result<(), Error> {
+    if checks.is_empty() || source.auth_method().is_none() {
+        return Ok(());
+    }
+    match evaluate(checks, source) {
+        Ok(Ok(msgs)) => {
+            msgs.iter().for_each(|msg| eprintln!("Preflight: {}", msg));
+        }
+        Ok(Err(msgs)) => {
+            msgs.iter().for_each(|msg| eprintln!("ERROR: {}", msg));
+            bail!("Insufficient permissions in vault, exiting...");
+        }
+        Err(e) => eprintln!("WARNING: Skipping capability preflight: {}", e),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{evaluate, list_check, mapping_checks};
+    use crate::{MappingKind, SecretMapping, VaultPath};
+    use vault::test_util::FakeVault;
+
+    fn path(engine: &str, p: &str) -> VaultPath {
+        VaultPath {
+            engine: engine.into(),
+            path: p.into(),
+        }
+    }
+
+    #[test]
+    fn reports_denied_paths_per_mapping() {
+        let vault = FakeVault::start();
+        vault.set_capabilities("secret/data/apps/web/db", &["read", "list"]);
+        vault.set_capabilities("secret/data/apps/web/api", &["deny"]);
+        vault.set_capabilities("database/creds/web", &["read"]);
+        vault.set_capabilities("secret/metadata/apps/web", &["read"]);
+        let mut client = vault.client();
+
+        let mappings = vec![
+            SecretMapping::new("db", path("secret", "/apps/web/db")),
+            SecretMapping::new("api", path("secret", "apps/web/api")),
+            SecretMapping::new("creds", path("database", "creds/web"))
+                .with_kind(MappingKind::Dynamic),
+        ];
+        let mut checks = mapping_checks(&mappings);
+        checks.push(list_check(&path("secret", "apps/web")));
+
+        let denied = evaluate(&checks, &mut client).unwrap().unwrap_err();
+        assert_eq!(
+            denied,
+            vec![
+                "Token cannot read secret/data/apps/web/api for secret 'api' (secret:apps/web/api) (capabilities: deny)",
+                "Token cannot list secret/metadata/apps/web for secret:apps/web (capabilities: read)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,558 @@
+//! # Secret Sources
+//!
+//! Commands read secret values through the [`SecretSource`] trait rather than a concrete
+//! client, so that backends other than Vault can be swapped in without changing command logic.
+//!
+//! ## Available sources
+//! * `vault` - A [`VaultClient`] configured from the environment.
+//! * `file:<dir>` - A [`FileSource`] reading a directory tree of YAML/JSON files, for offline use.
+//! * `replay:<cassette>` - A [`VaultClient`] replaying interactions recorded with `--record`.
+use failure::{bail, err_msg, Error, Fail};
+use serde_json::Value as JsonValue;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use vault::api::{CertificateRequest, LeaseInfo, PkiCertificate, TokenInfo};
+use vault::cassette::Redaction;
+use vault::secret::Secret;
+use vault::{SecretString, VaultClient, VaultClientError};
+
+const FILE_EXTENSIONS: &[&str] = &["yaml", "yml", "json"];
+
+/// Information about where a secret lives, used to annotate generated kubernetes objects.
+#[derive(Debug, Clone)]
+pub struct SecretMetadata {
+    pub uri: String,
+}
+
+/// The lease a dynamic secret was issued with.
+#[derive(Debug, Clone)]
+pub struct Lease {
+    pub id: String,
+    /// Seconds the secret is valid for from the time it was read.
+    pub duration: i64,
+}
+
+/// A dynamic secret, and its lease if the source issued one.
+#[derive(Debug)]
+pub struct LeasedSecret {
+    pub data: HashMap<String, SecretString>,
+    pub lease: Option<Lease>,
+}
+
+/// Errors returned by a [`SecretSource`].
+#[derive(Debug)]
+pub enum SourceError {
+    /// Nothing exists at the requested `engine:path`.
+    NotFound(String),
+    /// The source can't perform the named operation.
+    Unsupported(&'static str),
+    /// An error from the Vault client.
+    Vault(VaultClientError),
+    /// Any other failure, such as an unreadable or malformed secret file.
+    Other(Error),
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceError::NotFound(s) => write!(f, "Not Found: {}", s),
+            SourceError::Unsupported(op) => {
+                write!(f, "{} is not supported by this secret source", op)
+            }
+            SourceError::Vault(e) => e.fmt(f),
+            SourceError::Other(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Fail for SourceError {}
+
+impl From<VaultClientError> for SourceError {
+    fn from(e: VaultClientError) -> SourceError {
+        match e {
+            VaultClientError::NotFound(s) => SourceError::NotFound(s),
+            e => SourceError::Vault(e),
+        }
+    }
+}
+
+pub trait SecretSource {
+    /// Get the key/value pairs of the secret at `path` in `engine`.
+    fn get(
+        &mut self,
+        engine: &str,
+        path: &str,
+    ) -> Result<HashMap<String, SecretString>, SourceError>;
+
+    /// List the secret names found under `path` in `engine`.
+    fn list(&mut self, engine: &str, path: &str) -> Result<Vec<String>, SourceError>;
+
+    /// Describe the secret at `path` in `engine`.
+    fn metadata(&self, engine: &str, path: &str) -> SecretMetadata;
+
+    /// Read the dynamic secret at `path` in `engine` (ex. `database:creds/my-role`), which
+    /// generates new credentials on each read.
+    ///
+    /// Sources without dynamic secrets serve a static secret from the same path, with no lease.
+    fn get_leased(&mut self, engine: &str, path: &str) -> Result<LeasedSecret, SourceError> {
+        Ok(LeasedSecret {
+            data: self.get(engine, path)?,
+            lease: None,
+        })
+    }
+
+    /// Decrypt Transit `ciphertext` (ex. `vault:v1:...`) with the named `key` in `engine`.
+    fn decrypt(
+        &mut self,
+        _engine: &str,
+        _key: &str,
+        _ciphertext: &str,
+    ) -> Result<Secret<Vec<u8>>, SourceError> {
+        Err(SourceError::Unsupported("Transit decryption"))
+    }
+
+    /// The capabilities (ex. `read`, `list`) the source's token has on each logical path.
+    fn capabilities(
+        &mut self,
+        _paths: &[String],
+    ) -> Result<HashMap<String, Vec<String>>, SourceError> {
+        Err(SourceError::Unsupported("Capability lookup"))
+    }
+
+    /// Name of the auth method the source logged in with, if it authenticates.
+    fn auth_method(&self) -> Option<&str> {
+        None
+    }
+
+    /// Look up the properties of the source's client token.
+    fn lookup_self(&mut self) -> Result<TokenInfo, SourceError> {
+        Err(SourceError::Unsupported("Token lookup"))
+    }
+
+    /// Issue a certificate from the named `role` of the PKI `engine`.
+    fn issue_certificate(
+        &mut self,
+        _engine: &str,
+        _role: &str,
+        _request: &CertificateRequest,
+    ) -> Result<PkiCertificate, SourceError> {
+        Err(SourceError::Unsupported("Certificate issuing"))
+    }
+
+    /// Renew the lease `lease_id`, optionally requesting a new TTL of `increment` seconds.
+    fn renew_lease(
+        &mut self,
+        _lease_id: &str,
+        _increment: Option<i64>,
+    ) -> Result<Lease, SourceError> {
+        Err(SourceError::Unsupported("Lease renewal"))
+    }
+
+    /// Revoke the lease `lease_id`.
+    fn revoke_lease(&mut self, _lease_id: &str) -> Result<(), SourceError> {
+        Err(SourceError::Unsupported("Lease revocation"))
+    }
+
+    /// Look up the lease `lease_id`.
+    fn lookup_lease(&mut self, _lease_id: &str) -> Result<LeaseInfo, SourceError> {
+        Err(SourceError::Unsupported("Lease lookup"))
+    }
+}
+
+impl SecretSource for VaultClient {
+    fn get(
+        &mut self,
+        engine: &str,
+        path: &str,
+    ) -> Result<HashMap<String, SecretString>, SourceError> {
+        Ok(self.get_kv_secret(engine, path)?)
+    }
+
+    fn list(&mut self, engine: &str, path: &str) -> Result<Vec<String>, SourceError> {
+        Ok(self.list_kv_keys(engine, path)?)
+    }
+
+    fn metadata(&self, engine: &str, path: &str) -> SecretMetadata {
+        SecretMetadata {
+            uri: format!(
+                "{}ui/vault/secrets/{}/show{}",
+                self.vault_addr(),
+                engine,
+                with_leading_slash(path)
+            ),
+        }
+    }
+
+    fn get_leased(&mut self, engine: &str, path: &str) -> Result<LeasedSecret, SourceError> {
+        let resp = self.read(format!("{}{}", engine, with_leading_slash(path)))?;
+        let data = match resp.data {
+            Some(JsonValue::Object(m)) => m
+                .into_iter()
+                .filter_map(|(k, v)| match v {
+                    JsonValue::String(s) => Some((k, s.into())),
+                    JsonValue::Null => None,
+                    v => Some((k, v.to_string().into())),
+                })
+                .collect(),
+            _ => HashMap::new(),
+        };
+        let lease = if resp.lease_id.is_empty() {
+            None
+        } else {
+            Some(Lease {
+                id: resp.lease_id,
+                duration: resp.lease_duration,
+            })
+        };
+        Ok(LeasedSecret { data, lease })
+    }
+
+    fn decrypt(
+        &mut self,
+        engine: &str,
+        key: &str,
+        ciphertext: &str,
+    ) -> Result<Secret<Vec<u8>>, SourceError> {
+        Ok(self.transit_decrypt(engine, key, ciphertext)?)
+    }
+
+    fn capabilities(
+        &mut self,
+        paths: &[String],
+    ) -> Result<HashMap<String, Vec<String>>, SourceError> {
+        Ok(self.capabilities_self(paths)?)
+    }
+
+    fn auth_method(&self) -> Option<&str> {
+        Some(VaultClient::auth_method(self))
+    }
+
+    fn lookup_self(&mut self) -> Result<TokenInfo, SourceError> {
+        Ok(VaultClient::lookup_self(self)?)
+    }
+
+    fn issue_certificate(
+        &mut self,
+        engine: &str,
+        role: &str,
+        request: &CertificateRequest,
+    ) -> Result<PkiCertificate, SourceError> {
+        Ok(VaultClient::issue_certificate(self, engine, role, request)?)
+    }
+
+    fn renew_lease(
+        &mut self,
+        lease_id: &str,
+        increment: Option<i64>,
+    ) -> Result<Lease, SourceError> {
+        let resp = VaultClient::renew_lease(self, lease_id, increment)?;
+        Ok(Lease {
+            id: resp.lease_id,
+            duration: resp.lease_duration,
+        })
+    }
+
+    fn revoke_lease(&mut self, lease_id: &str) -> Result<(), SourceError> {
+        Ok(VaultClient::revoke_lease(self, lease_id)?)
+    }
+
+    fn lookup_lease(&mut self, lease_id: &str) -> Result<LeaseInfo, SourceError> {
+        Ok(VaultClient::lookup_lease(self, lease_id)?)
+    }
+}
+
+/// A secret source backed by a directory tree mirroring `engine/path`.
+///
+/// The secret `engine:apps/my-app/db` is read from `<root>/engine/apps/my-app/db.yaml`
+/// (or `.yml`/`.json`), which must contain a flat mapping of keys to values.
+/// Listing `engine:apps/my-app` returns the files in `<root>/engine/apps/my-app`
+/// without their extensions, and any subdirectories with a trailing `/`.
+pub struct FileSource {
+    root: PathBuf,
+}
+
+impl FileSource {
+    pub fn new<P: Into<PathBuf>>(root: P) -> FileSource {
+        FileSource { root: root.into() }
+    }
+
+    /// The location of `engine:path` under the root.  Paths that could point outside of it
+    /// (with `..`, or absolute once the leading `/` of `path` is dropped) are rejected.
+    fn base_path(&self, engine: &str, path: &str) -> Result<PathBuf, SourceError> {
+        let relative = Path::new(engine).join(path.trim_start_matches('/').trim_end_matches('/'));
+        let escapes = relative.components().any(|c| match c {
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => true,
+            Component::CurDir | Component::Normal(_) => false,
+        });
+        if escapes {
+            return Err(SourceError::Other(err_msg(format!(
+                "Secret path {}:{} is outside of the source directory",
+                engine, path
+            ))));
+        }
+        Ok(self.root.join(relative))
+    }
+
+    fn secret_file(&self, engine: &str, path: &str) -> Result<Option<PathBuf>, SourceError> {
+        let base = self.base_path(engine, path)?;
+        Ok(FILE_EXTENSIONS
+            .iter()
+            .map(|ext| {
+                let mut file = base.clone().into_os_string();
+                file.push(".");
+                file.push(ext);
+                PathBuf::from(file)
+            })
+            .find(|p| p.is_file()))
+    }
+}
+
+impl SecretSource for FileSource {
+    fn get(
+        &mut self,
+        engine: &str,
+        path: &str,
+    ) -> Result<HashMap<String, SecretString>, SourceError> {
+        let file = self
+            .secret_file(engine, path)?
+            .ok_or_else(|| SourceError::NotFound(format!("{}:{}", engine, path)))?;
+        let invalid = |e: &dyn fmt::Display| {
+            SourceError::Other(err_msg(format!("{}: {}", file.display(), e)))
+        };
+        let contents = fs::read_to_string(&file).map_err(|e| invalid(&e))?;
+        let value: Value = serde_yaml::from_str(&contents).map_err(|e| invalid(&e))?;
+        parse_secret_data(&value).map_err(|e| invalid(&e))
+    }
+
+    fn list(&mut self, engine: &str, path: &str) -> Result<Vec<String>, SourceError> {
+        let dir = self.base_path(engine, path)?;
+        let entries = fs::read_dir(&dir).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => SourceError::NotFound(format!("{}:{}", engine, path)),
+            _ => SourceError::Other(e.into()),
+        })?;
+        let mut keys = Vec::new();
+        for entry in entries {
+            let entry_path = entry.map_err(|e| SourceError::Other(e.into()))?.path();
+            if let Some(key) = list_key(&entry_path) {
+                keys.push(key);
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn metadata(&self, engine: &str, path: &str) -> SecretMetadata {
+        // Only used to describe the secret, so a rejected path is shown as given
+        let file = match self.secret_file(engine, path) {
+            Ok(Some(file)) => file,
+            _ => self.root.join(engine).join(path.trim_start_matches('/')),
+        };
+        SecretMetadata {
+            uri: format!("file://{}", file.display()),
+        }
+    }
+}
+
+fn list_key(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    if path.is_dir() {
+        return Some(format!("{}/", name));
+    }
+    let ext = path.extension()?.to_str()?;
+    if FILE_EXTENSIONS.contains(&ext) {
+        Some(path.file_stem()?.to_str()?.to_string())
+    } else {
+        None
+    }
+}
+
+fn parse_secret_data(value: &Value) -> Result<HashMap<String, SecretString>, Error> {
+    let mapping = match value {
+        Value::Mapping(m) => m,
+        Value::Null => return Ok(HashMap::new()),
+        _ => bail!("secret file must contain a mapping of keys to values"),
+    };
+    let mut data = HashMap::new();
+    for (k, v) in mapping {
+        let key = match k.as_str() {
+            Some(k) => k.to_string(),
+            None => bail!("secret keys must be strings"),
+        };
+        let val = match v {
+            Value::String(s) => s.clone(),
+            Value::Number(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            _ => bail!("value for key '{}' must be a scalar", key),
+        };
+        data.insert(key, val.into());
+    }
+    Ok(data)
+}
+
+/// Create the secret source described by `spec`: `vault`, `file:<dir>` or `replay:<cassette>`.
+///
+/// If `record` is given, Vault interactions are recorded to that cassette file.  Recorded
+/// secret values are encrypted with `VAULT_CASSETTE_KEY` if it is set, and redacted otherwise.
+pub fn from_spec(spec: &str, record: Option<&str>) -> Result<Box<dyn SecretSource>, Error> {
+    if let Some(dir) = spec.strip_prefix("file:") {
+        if record.is_some() {
+            bail!("Recording is only supported for the vault source");
+        }
+        let root = Path::new(dir);
+        if !root.is_dir() {
+            bail!("Secret source directory {} does not exist", root.display());
+        }
+        Ok(Box::new(FileSource::new(root)))
+    } else {
+        Ok(Box::new(vault_client(spec, record)?))
+    }
+}
+
+/// Create the vault client for the `vault` or `replay:<cassette>` source `spec`, recording to
+/// `record` like [`from_spec`].
+pub fn vault_client(spec: &str, record: Option<&str>) -> Result<VaultClient, Error> {
+    if record.is_some() && spec != "vault" {
+        bail!("Recording is only supported for the vault source");
+    }
+    if spec == "vault" {
+        let mut client = match VaultClient::from_env() {
+            Ok(c) => c,
+            Err(e) => bail!("Could not create vault client: {}", e),
+        };
+        if let Some(cassette) = record {
+            let redaction = match cassette_key() {
+                Some(key) => Redaction::Encrypt(key),
+                None => Redaction::Redact,
+            };
+            client.record_to(cassette, redaction)?;
+        }
+        Ok(client)
+    } else if let Some(cassette) = spec.strip_prefix("replay:") {
+        match VaultClient::replay(cassette, cassette_key().as_deref()) {
+            Ok(c) => Ok(c),
+            Err(e) => bail!("Could not load cassette {}: {}", cassette, e),
+        }
+    } else if spec.starts_with("file:") {
+        bail!("{} is not a vault source", spec)
+    } else {
+        bail!("Unknown secret source: {}", spec)
+    }
+}
+
+fn cassette_key() -> Option<String> {
+    env::var("VAULT_CASSETTE_KEY").ok()
+}
+
+/// Check that `spec` names a known secret source.
+pub fn validate_spec(spec: String) -> Result<(), String> {
+    if spec == "vault" || spec.starts_with("file:") || spec.starts_with("replay:") {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid source: {}.  Source should be 'vault', 'file:path/to/dir' or 'replay:path/to/cassette.json'",
+            spec
+        ))
+    }
+}
+
+fn with_leading_slash(s: &str) -> String {
+    let mut s = s.to_string();
+    if !s.starts_with('/') {
+        s.insert(0, '/');
+    }
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FileSource, SecretSource, SourceError};
+    use std::fs;
+    use tempfile::TempDir;
+    use vault::VaultClient;
+
+    fn get_test_source() -> (TempDir, FileSource) {
+        let dir = tempfile::tempdir().unwrap();
+        let app = dir.path().join("secret/apps/my-app");
+        fs::create_dir_all(app.join("nested")).unwrap();
+        fs::write(
+            app.join("db.yaml"),
+            "username: app\npassword: hunter2\nport: 5432\n",
+        )
+        .unwrap();
+        fs::write(app.join("api.json"), r#"{"token": "abc123"}"#).unwrap();
+        fs::write(app.join("README.md"), "not a secret").unwrap();
+        let source = FileSource::new(dir.path());
+        (dir, source)
+    }
+
+    #[test]
+    fn vault_metadata_links_to_ui() {
+        let client =
+            VaultClient::from_client_token("https://vault.example.com/".parse().unwrap(), "t");
+        assert_eq!(
+            client.metadata("secret", "apps/my-app").uri,
+            "https://vault.example.com/ui/vault/secrets/secret/show/apps/my-app"
+        );
+        assert_eq!(
+            client.metadata("secret", "/apps/my-app").uri,
+            "https://vault.example.com/ui/vault/secrets/secret/show/apps/my-app"
+        );
+    }
+
+    #[test]
+    fn file_source_reads_yaml_and_json() {
+        let (_dir, mut source) = get_test_source();
+        let db = source.get("secret", "/apps/my-app/db").unwrap();
+        assert_eq!(db["username"].expose(), "app");
+        assert_eq!(db["password"].expose(), "hunter2");
+        assert_eq!(db["port"].expose(), "5432");
+        let api = source.get("secret", "apps/my-app/api").unwrap();
+        assert_eq!(api["token"].expose(), "abc123");
+    }
+
+    #[test]
+    fn file_source_lists_secrets() {
+        let (_dir, mut source) = get_test_source();
+        let keys = source.list("secret", "apps/my-app/").unwrap();
+        assert_eq!(keys, vec!["api", "db", "nested/"]);
+    }
+
+    #[test]
+    fn file_source_rejects_paths_outside_root() {
+        let (dir, mut source) = get_test_source();
+        fs::write(dir.path().join("outside.yaml"), "leaked: true\n").unwrap();
+        for (engine, path) in &[
+            ("secret", "../outside"),
+            ("secret", "apps/../../outside"),
+            ("..", "outside"),
+            ("/etc", "passwd"),
+        ] {
+            match source.get(engine, path) {
+                Err(SourceError::Other(e)) => assert!(e.to_string().contains("outside of")),
+                other => panic!(
+                    "Expected {}:{} to be rejected, got {:?}",
+                    engine, path, other
+                ),
+            }
+        }
+        assert!(source.list("secret", "apps/..").is_err());
+    }
+
+    #[test]
+    fn file_source_missing_secret_is_not_found() {
+        let (_dir, mut source) = get_test_source();
+        match source.get("secret", "apps/my-app/missing") {
+            Err(SourceError::NotFound(p)) => assert_eq!(p, "secret:apps/my-app/missing"),
+            other => panic!("Expected NotFound, got {:?}", other),
+        }
+        match source.list("secret", "apps/other-app") {
+            Err(SourceError::NotFound(_)) => {}
+            other => panic!("Expected NotFound, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! # Transit
+//!
+//! Kubernetes Secrets can be kept in git with their values encrypted by Vault's Transit engine
+//! (ex. `password: vault:v1:...`).  This module finds such Secrets in a [`Corpus`] and decrypts
+//! them at render time, so Vault remains the only holder of the key.
+//!
+//! Values in `data` that aren't ciphertext must be base64 encoded as usual, values in
+//! `stringData` are used as-is.
+use crate::generate::SecretsTemplate;
+use crate::haystack::Corpus;
+use crate::source::SecretSource;
+use crate::VaultPath;
+use askama::Template;
+use failure::{bail, Error};
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+use vault::secret::Secret;
+
+const CIPHERTEXT_PREFIX: &str = "vault:v";
+
+#[derive(Debug)]
+enum SecretValue {
+    Ciphertext(String),
+    Plain(Secret<Vec<u8>>),
+    /// A `data` value that isn't valid base64, and why.
+    Invalid(String),
+}
+
+#[derive(Debug)]
+pub struct EncryptedSecret {
+    name: String,
+    secret_type: Option<String>,
+    data: HashMap<String, SecretValue>,
+}
+
+fn is_ciphertext(s: &str) -> bool {
+    s.starts_with(CIPHERTEXT_PREFIX)
+}
+
+fn string_entries(m: &Mapping, key: &str) -> Vec<(String, String)> {
+    m.get(&key.into())
+        .and_then(Value::as_mapping)
+        .map(|data| {
+            data.iter()
+                .filter_map(|(k, v)| Some((k.as_str()?.to_string(), v.as_str()?.to_string())))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn filter_map_encrypted_secret(m: &Mapping) -> Option<EncryptedSecret> {
+    if m.get(&"kind".into())?.as_str()? != "Secret" {
+        return None;
+    }
+    let name = m
+        .get(&"metadata".into())?
+        .as_mapping()?
+        .get(&"name".into())?
+        .as_str()?
+        .to_string();
+    let secret_type = m
+        .get(&"type".into())
+        .and_then(Value::as_str)
+        .map(str::to_string);
+
+    let mut data = HashMap::new();
+    for (k, v) in string_entries(m, "data") {
+        let value = if is_ciphertext(&v) {
+            SecretValue::Ciphertext(v)
+        } else {
+            match base64::decode(&v) {
+                Ok(bytes) => SecretValue::Plain(Secret::new(bytes)),
+                Err(e) => SecretValue::Invalid(e.to_string()),
+            }
+        };
+        data.insert(k, value);
+    }
+    for (k, v) in string_entries(m, "stringData") {
+        let value = if is_ciphertext(&v) {
+            SecretValue::Ciphertext(v)
+        } else {
+            SecretValue::Plain(Secret::new(v.into_bytes()))
+        };
+        data.insert(k, value);
+    }
+
+    if data
+        .values()
+        .any(|v| matches!(v, SecretValue::Ciphertext(_)))
+    {
+        Some(EncryptedSecret {
+            name,
+            secret_type,
+            data,
+        })
+    } else {
+        None
+    }
+}
+
+/// Find all kubernetes Secrets in `corpus` with at least one Transit-encrypted value.
+pub fn find_encrypted_secrets(corpus: &Corpus) -> Vec<EncryptedSecret> {
+    corpus.filter_map_mappings(filter_map_encrypted_secret)
+}
+
+/// Decrypt `secrets` with the Transit key at `transit_key` (ex. `transit:my-key`) and render
+/// them as kubernetes Secrets for `namespace`.  Nothing is decrypted if any plain `data`
+/// value isn't valid base64.
+pub fn create_decrypted_secret_templates(
+    secrets: Vec<EncryptedSecret>,
+    namespace: &str,
+    transit_key: &VaultPath,
+    source: &mut dyn SecretSource,
+) -> Result<Vec<String>, Error> {
+    let mut invalid = Vec::new();
+    for secret in &secrets {
+        for (key, value) in &secret.data {
+            if let SecretValue::Invalid(e) = value {
+                invalid.push(format!(
+                    "Key '{}' of secret '{}' is not valid base64: {}",
+                    key, secret.name, e
+                ));
+            }
+        }
+    }
+    if !invalid.is_empty() {
+        invalid.sort();
+        bail!("{}", invalid.join("\n"));
+    }
+
+    let mut templates = Vec::new();
+    for secret in secrets {
+        let mut data = HashMap::new();
+        for (key, value) in secret.data {
+            let plaintext = match value {
+                SecretValue::Plain(p) => p,
+                SecretValue::Invalid(_) => unreachable!("invalid values are reported above"),
+                SecretValue::Ciphertext(c) => {
+                    match source.decrypt(&transit_key.engine, &transit_key.path, &c) {
+                        Ok(p) => p,
+                        Err(e) => bail!(
+                            "Could not decrypt key '{}' of secret '{}': {}",
+                            key,
+                            secret.name,
+                            e
+                        ),
+                    }
+                }
+            };
+            data.insert(key, plaintext);
+        }
+        let metadata = source.metadata(&transit_key.engine, &transit_key.path);
+        let mut template = SecretsTemplate::new(&metadata.uri, &secret.name, namespace, data);
+        if let Some(t) = &secret.secret_type {
+            template = template.with_type(t);
+        }
+        templates.push(template.render()?);
+    }
+    Ok(templates)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{create_decrypted_secret_templates, find_encrypted_secrets, SecretValue};
+    use crate::haystack::Corpus;
+    use crate::source::SecretSource;
+    use crate::VaultPath;
+    use serde_yaml::Value;
+    use vault::test_util::FakeVault;
+
+    fn transit_key() -> VaultPath {
+        VaultPath {
+            engine: "transit".into(),
+            path: "my-app".into(),
+        }
+    }
+
+    static CONTENTS: &str = r#"---
+apiVersion: v1
+kind: Secret
+metadata:
+  name: db
+type: Opaque
+data:
+  username: d2Vi
+  password: vault:v1:abcdef
+stringData:
+  host: db.internal
+---
+apiVersion: v1
+kind: Secret
+metadata:
+  name: plain
+stringData:
+  host: db.internal
+"#;
+
+    #[test]
+    fn finds_only_encrypted_secrets() {
+        let corpus = Corpus::from_reader(CONTENTS.as_bytes()).unwrap();
+        let secrets = find_encrypted_secrets(&corpus);
+        assert_eq!(secrets.len(), 1);
+        let secret = &secrets[0];
+        assert_eq!(secret.name, "db");
+        assert_eq!(secret.secret_type.as_deref(), Some("Opaque"));
+        assert_eq!(secret.data.len(), 3);
+    }
+
+    #[test]
+    fn decrypts_with_transit_source() {
+        let vault = FakeVault::start();
+        vault.mount_transit("transit");
+        let mut client = vault.client();
+        let ciphertext = client
+            .transit_encrypt("transit", "my-app", b"hunter2")
+            .unwrap();
+        let plaintext = client.decrypt("transit", "my-app", &ciphertext).unwrap();
+        assert_eq!(plaintext.expose(), b"hunter2");
+
+        let corpus = Corpus::from_reader(CONTENTS.as_bytes()).unwrap();
+        let secrets = find_encrypted_secrets(&corpus);
+        match &secrets[0].data["username"] {
+            SecretValue::Plain(p) => assert_eq!(p.expose(), b"web"),
+            other => panic!("Expected plain value, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn renders_decrypted_secrets() {
+        let vault = FakeVault::start();
+        vault.mount_transit("transit");
+        let mut client = vault.client();
+        let ciphertext = client
+            .transit_encrypt("transit", "my-app", b"hunter2")
+            .unwrap();
+        let contents = CONTENTS.replace("vault:v1:abcdef", &ciphertext);
+        let corpus = Corpus::from_reader(contents.as_bytes()).unwrap();
+
+        let secrets = find_encrypted_secrets(&corpus);
+        let templates =
+            create_decrypted_secret_templates(secrets, "apps", &transit_key(), &mut client)
+                .unwrap();
+        assert_eq!(templates.len(), 1);
+        let doc: Value = serde_yaml::from_str(&templates[0]).unwrap();
+        assert_eq!(doc["metadata"]["name"].as_str(), Some("db"));
+        assert_eq!(doc["metadata"]["namespace"].as_str(), Some("apps"));
+        assert_eq!(doc["type"].as_str(), Some("Opaque"));
+        let data = &doc["data"];
+        assert_eq!(data["username"].as_str(), Some("d2Vi"));
+        assert_eq!(data["password"].as_str(), Some(&*base64::encode("hunter2")));
+        assert_eq!(data["host"].as_str(), Some(&*base64::encode("db.internal")));
+    }
+
+    #[test]
+    fn reports_invalid_base64_values() {
+        let vault = FakeVault::start();
+        vault.mount_transit("transit");
+        let contents = CONTENTS.replace("username: d2Vi", "username: not*base64");
+        let corpus = Corpus::from_reader(contents.as_bytes()).unwrap();
+
+        let secrets = find_encrypted_secrets(&corpus);
+        assert_eq!(secrets.len(), 1, "secret with an invalid value was dropped");
+        let err =
+            create_decrypted_secret_templates(secrets, "apps", &transit_key(), &mut vault.client())
+                .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Key 'username' of secret 'db' is not valid base64"));
+    }
+}
@@ -1,124 +1,436 @@
-use crate::chart::{grouped_secret_key_refs, grouped_secret_refs, referenced_k8s_secret_names};
+use crate::chart::{
+    describe_location, find_secret_refs, find_vol_item_refs, grouped_secret_key_refs,
+    optional_k8s_secret_names, SecretKeyRef,
+};
+use crate::generate::TLS_SECRET_KEYS;
 use crate::haystack::Corpus;
-use crate::SecretMapping;
-use std::collections::HashMap;
-use vault::VaultClient;
+use crate::source::{SecretSource, SourceError};
+use crate::{MappingKind, SecretMapping};
+use std::collections::{HashMap, HashSet};
+
+/// Messages from verifying the secrets of a chart against a source.
+#[derive(Debug, Default)]
+pub struct Verification {
+    /// Secrets and keys that were found.
+    pub verified: Vec<String>,
+    /// Secrets and keys marked `optional: true` that weren't found, which pods start without.
+    pub warnings: Vec<String>,
+    /// Required secrets and keys that weren't found, and errors reading the source.
+    pub errors: Vec<String>,
+}
 
 fn verify_paths_exist<T: AsRef<str>>(
     k8s_secret_names: &[String],
+    optional_names: &HashSet<String>,
     engine: T,
     path: T,
-    client: &mut VaultClient,
-) -> Result<Vec<String>, Vec<String>> {
-    let mut messages = Vec::new();
-    let mut verified_paths = Vec::new();
-    match client.list_kv_keys(&engine, &path) {
+    source: &mut dyn SecretSource,
+) -> Verification {
+    let mut verification = Verification::default();
+    match source.list(engine.as_ref(), path.as_ref()) {
         Ok(keys) => {
             for secret in k8s_secret_names {
                 if keys.contains(secret) {
-                    verified_paths.push(format!(
+                    verification.verified.push(format!(
                         "Secret '{}' maps to {}:{}/{}",
                         secret,
                         engine.as_ref(),
                         path.as_ref(),
                         secret
                     ));
+                } else if optional_names.contains(secret) {
+                    verification.warnings.push(format!(
+                        "Optional secret '{}' not found in {}:{}",
+                        secret,
+                        engine.as_ref(),
+                        path.as_ref()
+                    ));
+                } else {
+                    verification.errors.push(format!(
+                        "Secret '{}' not found in {}:{}",
+                        secret,
+                        engine.as_ref(),
+                        path.as_ref()
+                    ));
                 }
             }
         }
         Err(e) => {
-            messages.push(format!("Client Error: {}", e));
+            verification.errors.push(format!("Client Error: {}", e));
         }
     }
-
-    if !messages.is_empty() {
-        Err(messages)
-    } else {
-        Ok(verified_paths)
-    }
+    verification
 }
 
+/// Verify that the Kubernetes Secrets `k8s_secret_names` referenced by `corpus` are under
+/// `path`.
 pub fn verify_mapping<T: AsRef<str>>(
+    k8s_secret_names: &[String],
     corpus: &Corpus,
     engine: T,
     path: T,
-    client: &mut VaultClient,
-) -> Result<Vec<String>, Vec<String>> {
-    let secrets = referenced_k8s_secret_names(&corpus);
+    source: &mut dyn SecretSource,
+) -> Verification {
     verify_paths_exist(
-        &secrets.into_iter().collect::<Vec<String>>(),
+        k8s_secret_names,
+        &optional_k8s_secret_names(corpus),
         engine,
         path,
-        client,
+        source,
     )
 }
 
 pub fn verify_secrets_exist_in_vault(
     secret_mappings: &[SecretMapping],
     corpus: &Corpus,
-    client: &mut VaultClient,
-) -> Result<Vec<String>, Vec<String>> {
-    let env_secrets: HashMap<String, Vec<String>> = grouped_secret_key_refs(&corpus);
-    let secret_refs = grouped_secret_refs(&corpus);
-    let mut messages = Vec::new();
-    let mut verified_paths = Vec::new();
-    for secret_name in secret_refs {
+    source: &mut dyn SecretSource,
+) -> Verification {
+    let mut env_secrets: HashMap<String, Vec<SecretKeyRef>> = grouped_secret_key_refs(corpus);
+    // Keys selected by secret volumes must exist for the pod to start, same as secretKeyRefs
+    for item_ref in find_vol_item_refs(corpus) {
+        env_secrets
+            .entry(item_ref.name.to_string())
+            .or_default()
+            .push(item_ref);
+    }
+    let secret_refs = find_secret_refs(corpus);
+    let mut verification = Verification::default();
+    for secret_ref in secret_refs {
+        let secret_name = &secret_ref.name;
         if let Some(m) = secret_mappings
             .iter()
-            .find(|m| m.kubernetes_name == secret_name)
+            .find(|m| &m.kubernetes_name == secret_name)
         {
-            match client.get_kv_secret(&m.vault_path.engine, &m.vault_path.path) {
-                Ok(mapping) => {
-                    if mapping.is_empty() {
-                        messages.push(format!(
-                            "No secrets for '{}' found at {}:{}",
-                            secret_name, m.vault_path.engine, m.vault_path.path
-                        ));
-                    } else {
-                        verified_paths.push(format!(
-                            "{} maps to {}:{}",
-                            secret_name, m.vault_path.engine, m.vault_path.path
-                        ));
-                    }
+            // Reading a dynamic secret or certificate issues new credentials, so only the
+            // mapping is checked
+            if m.kind.is_dynamic() {
+                verification.verified.push(format!(
+                    "{} maps to dynamic secret {}:{}",
+                    secret_name, m.vault_path.engine, m.vault_path.path
+                ));
+                continue;
+            }
+            if let MappingKind::Certificate(_) = m.kind {
+                verification.verified.push(format!(
+                    "{} maps to a certificate issued by {}:{}",
+                    secret_name, m.vault_path.engine, m.vault_path.path
+                ));
+                continue;
+            }
+            let missing = match source.get(&m.vault_path.engine, &m.vault_path.path) {
+                Ok(mapping) => mapping.is_empty(),
+                Err(SourceError::NotFound(_)) if secret_ref.optional => true,
+                Err(e) => {
+                    verification
+                        .errors
+                        .push(format!("Secret source error: {}", e));
+                    continue;
                 }
-                Err(e) => messages.push(format!("Vault client error: {}", e)),
+            };
+            if missing && secret_ref.optional {
+                verification.warnings.push(format!(
+                    "Optional secret '{}' not found at {}:{} (referenced by {})",
+                    secret_name,
+                    m.vault_path.engine,
+                    m.vault_path.path,
+                    describe_location(corpus, &secret_ref.location)
+                ));
+            } else if missing {
+                verification.errors.push(format!(
+                    "No secrets for '{}' found at {}:{} (referenced by {})",
+                    secret_name,
+                    m.vault_path.engine,
+                    m.vault_path.path,
+                    describe_location(corpus, &secret_ref.location)
+                ));
+            } else {
+                verification.verified.push(format!(
+                    "{} maps to {}:{}",
+                    secret_name, m.vault_path.engine, m.vault_path.path
+                ));
             }
         }
     }
-    for (secret_name, keys) in env_secrets {
+    for (secret_name, key_refs) in env_secrets {
+        let all_optional = key_refs.iter().all(|r| r.optional);
         if let Some(m) = secret_mappings
             .iter()
             .find(|m| m.kubernetes_name == secret_name)
         {
-            match client.get_kv_secret(&m.vault_path.engine, &m.vault_path.path) {
-                Ok(mapping) => {
-                    for key in keys {
-                        if mapping.contains_key(&key) {
-                            verified_paths.push(format!(
-                                "{}:{} maps to {}:{}/{}",
-                                secret_name, key, m.vault_path.engine, m.vault_path.path, key
-                            ));
-                        } else {
-                            messages.push(format!(
-                                "Key '{}' for secret '{}' not found in {}:{}",
-                                key, secret_name, m.vault_path.engine, m.vault_path.path
-                            ));
-                        }
+            if m.kind.is_dynamic() {
+                for key_ref in key_refs {
+                    verification.verified.push(format!(
+                        "{}:{} maps to dynamic secret {}:{}",
+                        secret_name, key_ref.key, m.vault_path.engine, m.vault_path.path
+                    ));
+                }
+                continue;
+            }
+            if let MappingKind::Certificate(_) = m.kind {
+                for key_ref in key_refs {
+                    if TLS_SECRET_KEYS.contains(&key_ref.key.as_str()) {
+                        verification.verified.push(format!(
+                            "{}:{} maps to a certificate issued by {}:{}",
+                            secret_name, key_ref.key, m.vault_path.engine, m.vault_path.path
+                        ));
+                    } else {
+                        verification.errors.push(format!(
+                            "Key '{}' for secret '{}' is not part of a certificate issued by {}:{}, expected one of {} (referenced by {})",
+                            key_ref.key,
+                            secret_name,
+                            m.vault_path.engine,
+                            m.vault_path.path,
+                            TLS_SECRET_KEYS.join(", "),
+                            describe_location(corpus, &key_ref.location)
+                        ));
+                    }
+                }
+                continue;
+            }
+            let mapping = match source.get(&m.vault_path.engine, &m.vault_path.path) {
+                Ok(mapping) => mapping,
+                Err(SourceError::NotFound(_)) if all_optional => HashMap::new(),
+                Err(e) => {
+                    verification
+                        .errors
+                        .push(format!("Secret source error: {}", e));
+                    continue;
+                }
+            };
+            for key_ref in key_refs {
+                let key = &key_ref.key;
+                if mapping.contains_key(key) {
+                    verification.verified.push(format!(
+                        "{}:{} maps to {}:{}/{}",
+                        secret_name, key, m.vault_path.engine, m.vault_path.path, key
+                    ));
+                } else {
+                    let label = if key_ref.optional {
+                        "Optional key"
+                    } else {
+                        "Key"
+                    };
+                    let message = format!(
+                        "{} '{}' for secret '{}' not found in {}:{} (referenced by {})",
+                        label,
+                        key,
+                        secret_name,
+                        m.vault_path.engine,
+                        m.vault_path.path,
+                        describe_location(corpus, &key_ref.location)
+                    );
+                    if key_ref.optional {
+                        verification.warnings.push(message);
+                    } else {
+                        verification.errors.push(message);
                     }
                 }
-                Err(e) => messages.push(format!("Vault client error: {}", e)),
             }
         } else {
-            messages.push(format!(
-                "Couldn't find a vault mapping for kubernetes secret {}",
-                secret_name
-            ));
+            let locations: Vec<String> = key_refs
+                .iter()
+                .map(|r| describe_location(corpus, &r.location))
+                .collect();
+            let message = format!(
+                "Couldn't find a vault mapping for kubernetes secret {} (referenced by {})",
+                secret_name,
+                locations.join(", ")
+            );
+            if all_optional {
+                verification.warnings.push(message);
+            } else {
+                verification.errors.push(message);
+            }
         }
     }
+    verification
+}
+
+#[cfg(test)]
+mod test {
+    use super::{verify_mapping, verify_secrets_exist_in_vault};
+    use crate::chart::referenced_k8s_secret_names;
+    use crate::haystack::Corpus;
+    use crate::{MappingKind, SecretMapping, VaultPath};
+    use vault::api::CertificateRequest;
+    use vault::test_util::FakeVault;
+
+    static CONTENTS: &str = r#"---
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: web
+spec:
+  template:
+    spec:
+      containers:
+        - name: app
+          envFrom:
+            - secretRef:
+                name: app-env
+            - secretRef:
+                name: feature-flags
+                optional: true
+          env:
+            - name: DB_PASSWORD
+              valueFrom:
+                secretKeyRef:
+                  name: db
+                  key: password
+            - name: SENTRY_DSN
+              valueFrom:
+                secretKeyRef:
+                  name: db
+                  key: sentry-dsn
+                  optional: true
+          volumeMounts:
+            - name: certs
+              mountPath: /etc/tls
+      volumes:
+        - name: certs
+          secret:
+            secretName: web-tls
+            items:
+              - key: tls.crt
+                path: cert.pem
+              - key: tls.key
+                path: key.pem
+"#;
 
-    if !messages.is_empty() {
-        Err(messages)
-    } else {
-        Ok(verified_paths)
+    fn get_test_corpus() -> Corpus {
+        Corpus::from_reader(CONTENTS.as_bytes()).unwrap()
+    }
+
+    fn get_test_vault() -> FakeVault {
+        let vault = FakeVault::start();
+        vault.mount_kv2("secret");
+        vault.put_secret("secret", "apps/web/app-env", &[("LOG_LEVEL", "debug")]);
+        vault.put_secret("secret", "apps/web/db", &[("username", "web")]);
+        vault.put_secret("secret", "apps/web/web-tls", &[("tls.crt", "cert")]);
+        vault
+    }
+
+    fn path(p: &str) -> VaultPath {
+        VaultPath {
+            engine: "secret".into(),
+            path: p.into(),
+        }
+    }
+
+    #[test]
+    fn verifies_secrets_in_path() {
+        let vault = get_test_vault();
+        let mut client = vault.client();
+        let corpus = get_test_corpus();
+        let names: Vec<String> = referenced_k8s_secret_names(&corpus).into_iter().collect();
+        let verification = verify_mapping(&names, &corpus, "secret", "apps/web", &mut client);
+        assert!(verification.errors.is_empty());
+        assert_eq!(
+            verification.warnings,
+            vec!["Optional secret 'feature-flags' not found in secret:apps/web"]
+        );
+        let mut verified = verification.verified;
+        verified.sort();
+        assert_eq!(
+            verified,
+            vec![
+                "Secret 'app-env' maps to secret:apps/web/app-env",
+                "Secret 'db' maps to secret:apps/web/db",
+                "Secret 'web-tls' maps to secret:apps/web/web-tls",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_missing_secrets_in_path() {
+        let vault = FakeVault::start();
+        vault.mount_kv2("secret");
+        vault.put_secret("secret", "apps/web/app-env", &[("LOG_LEVEL", "debug")]);
+        vault.put_secret("secret", "apps/web/web-tls", &[("tls.crt", "cert")]);
+        let mut client = vault.client();
+        let corpus = get_test_corpus();
+        let names: Vec<String> = referenced_k8s_secret_names(&corpus).into_iter().collect();
+        let verification = verify_mapping(&names, &corpus, "secret", "apps/web", &mut client);
+        assert_eq!(
+            verification.errors,
+            vec!["Secret 'db' not found in secret:apps/web"]
+        );
+        assert_eq!(verification.verified.len(), 2);
+
+        // Secrets generated from other mappings aren't looked for under the path.
+        let names: Vec<String> = names.into_iter().filter(|n| n != "db").collect();
+        let verification = verify_mapping(&names, &corpus, "secret", "apps/web", &mut client);
+        assert!(verification.errors.is_empty());
+    }
+
+    #[test]
+    fn reports_missing_keys() {
+        let vault = get_test_vault();
+        let mut client = vault.client();
+        let mappings = vec![
+            SecretMapping::new("app-env", path("apps/web/app-env")),
+            SecretMapping::new("db", path("apps/web/db")),
+            SecretMapping::new("web-tls", path("apps/web/web-tls")),
+            SecretMapping::new("feature-flags", path("apps/web/feature-flags")),
+        ];
+        let verification =
+            verify_secrets_exist_in_vault(&mappings, &get_test_corpus(), &mut client);
+        let mut warnings = verification.warnings;
+        warnings.sort();
+        assert_eq!(
+            warnings,
+            vec![
+                "Optional key 'sentry-dsn' for secret 'db' not found in secret:apps/web/db \
+                  (referenced by Deployment web container `app` env[1])",
+                "Optional secret 'feature-flags' not found at secret:apps/web/feature-flags \
+                  (referenced by Deployment web container `app` envFrom[1])",
+            ]
+        );
+        let mut errors = verification.errors;
+        errors.sort();
+        assert_eq!(
+            errors,
+            vec![
+                "Key 'password' for secret 'db' not found in secret:apps/web/db \
+                  (referenced by Deployment web container `app` env[0])",
+                "Key 'tls.key' for secret 'web-tls' not found in secret:apps/web/web-tls \
+                  (referenced by Deployment web volume `certs` items[1])",
+            ]
+        );
+    }
+
+    #[test]
+    fn verifies_certificate_keys() {
+        let vault = get_test_vault();
+        let mut client = vault.client();
+        let pki = VaultPath {
+            engine: "pki".into(),
+            path: "internal".into(),
+        };
+        let mappings = vec![
+            SecretMapping::new("app-env", path("apps/web/app-env")),
+            SecretMapping::new("db", path("apps/web/db")),
+            SecretMapping::new("web-tls", pki)
+                .with_kind(MappingKind::Certificate(CertificateRequest::default())),
+        ];
+        let contents = CONTENTS.replace("key: tls.key", "key: tls.pem");
+        let corpus = Corpus::from_reader(contents.as_bytes()).unwrap();
+        let verification = verify_secrets_exist_in_vault(&mappings, &corpus, &mut client);
+        assert!(verification
+            .verified
+            .contains(&"web-tls:tls.crt maps to a certificate issued by pki:internal".to_string()));
+        let errors: Vec<&String> = verification
+            .errors
+            .iter()
+            .filter(|e| e.contains("web-tls"))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "Key 'tls.pem' for secret 'web-tls' is not part of a certificate issued by \
+                  pki:internal, expected one of tls.crt, tls.key, ca.crt \
+                  (referenced by Deployment web volume `certs` items[1])"
+            ]
+        );
     }
 }
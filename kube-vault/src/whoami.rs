@@ -0,0 +1,71 @@
+use crate::source::SecretSource;
+use failure::{bail, Error};
+use vault::api::TokenInfo;
+
+fn list_or_none(items: &[String]) -> String {
+    if items.is_empty() {
+        "(None)".to_string()
+    } else {
+        items.join(", ")
+    }
+}
+
+fn format_token_info(auth_method: &str, info: &TokenInfo) -> Vec<String> {
+    let mut lines = vec![
+        format!("Auth Method: {} ({})", auth_method, info.path),
+        format!("Display Name: {}", info.display_name),
+        format!("Policies: {}", list_or_none(&info.policies)),
+    ];
+    if !info.identity_policies.is_empty() {
+        lines.push(format!(
+            "Identity Policies: {}",
+            list_or_none(&info.identity_policies)
+        ));
+    }
+    if info.entity_id.is_empty() {
+        lines.push("Entity: (None)".to_string());
+    } else {
+        lines.push(format!("Entity: {}", info.entity_id));
+    }
+    match &info.expire_time {
+        Some(expires) => lines.push(format!("TTL: {}s (expires {})", info.ttl, expires)),
+        None => lines.push("TTL: (Never expires)".to_string()),
+    }
+    lines.push(format!("Renewable: {}", info.renewable));
+    lines
+}
+
+/// Print the identity, policies and TTL of the token `source` is using.
+pub fn print_token_info(source: &mut dyn SecretSource) -> Result<(), Error> {
+    let info = match source.lookup_self() {
+        Ok(info) => info,
+        Err(e) => bail!("Could not look up token: {}", e),
+    };
+    let auth_method = source.auth_method().unwrap_or("unknown");
+    for line in format_token_info(auth_method, &info) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::format_token_info;
+    use crate::source::SecretSource;
+    use vault::test_util::FakeVault;
+    use vault::VaultClient;
+
+    #[test]
+    fn formats_token_info() {
+        let vault = FakeVault::start();
+        vault.add_github_token("gh", &["default", "deploy"]);
+        let mut client = VaultClient::github(vault.addr().clone(), "gh");
+        let source: &mut dyn SecretSource = &mut client;
+        let info = source.lookup_self().unwrap();
+        let lines = format_token_info(source.auth_method().unwrap(), &info);
+        assert_eq!(lines[0], "Auth Method: github (auth/github/login)");
+        assert_eq!(lines[2], "Policies: default, deploy");
+        assert!(lines[3].starts_with("Entity: fake-entity-"));
+        assert_eq!(lines[5], "Renewable: true");
+    }
+}
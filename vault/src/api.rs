@@ -1,21 +1,45 @@
+use crate::secret::SecretString;
 use serde_json::Value;
 use std::collections::HashMap;
 
 #[derive(Debug, Deserialize)]
 pub struct VaultResponse<D> {
     pub request_id: String,
+    #[serde(default)]
     pub lease_id: String,
+    #[serde(default)]
     pub renewable: bool,
+    #[serde(default)]
     pub lease_duration: i64,
     pub data: Option<D>,
-    pub wrap_info: Option<Value>,
-    pub warnings: Option<Value>,
+    pub wrap_info: Option<WrapInfo>,
+    pub warnings: Option<Vec<String>>,
     pub auth: Option<AuthInfo>,
 }
 
+/// A response-wrapping token, returned in place of the response data.
+#[derive(Debug, Deserialize)]
+pub struct WrapInfo {
+    pub token: SecretString,
+    pub accessor: String,
+    /// Seconds the token is valid for from `creation_time`.
+    pub ttl: i64,
+    pub creation_time: String,
+    pub creation_path: String,
+    #[serde(default)]
+    pub wrapped_accessor: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WrappingLookup {
+    pub creation_path: String,
+    pub creation_time: String,
+    pub creation_ttl: i64,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct AuthInfo {
-    pub client_token: String,
+    pub client_token: SecretString,
     pub accessor: String,
     pub policies: Vec<String>,
     pub token_policies: Vec<String>,
@@ -27,6 +51,82 @@ pub struct AuthInfo {
     pub orphan: bool,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct AppRoleSecretId {
+    pub secret_id: SecretString,
+}
+
+/// The properties of a client token, from `auth/token/lookup-self`.
+#[derive(Debug, Deserialize)]
+pub struct TokenInfo {
+    pub accessor: String,
+    pub display_name: String,
+    #[serde(default)]
+    pub entity_id: String,
+    /// Expiry as RFC 3339, or `None` for tokens that never expire (ex. root tokens).
+    pub expire_time: Option<String>,
+    /// The login path the token was created by (ex. `auth/approle/login`).
+    pub path: String,
+    pub policies: Vec<String>,
+    #[serde(default)]
+    pub identity_policies: Vec<String>,
+    pub renewable: bool,
+    /// Seconds remaining until the token expires, or `0` if it never expires.
+    pub ttl: i64,
+    #[serde(rename = "type")]
+    pub token_type: String,
+}
+
+/// The response of `sys/health`, which isn't wrapped in a `VaultResponse`.
+#[derive(Debug, Deserialize)]
+pub struct HealthStatus {
+    pub initialized: bool,
+    pub sealed: bool,
+    pub standby: bool,
+    #[serde(default)]
+    pub performance_standby: bool,
+    pub server_time_utc: i64,
+    pub version: String,
+    pub cluster_name: Option<String>,
+}
+
+/// The response of `sys/seal-status`, which isn't wrapped in a `VaultResponse`.
+#[derive(Debug, Deserialize)]
+pub struct SealStatus {
+    #[serde(rename = "type")]
+    pub seal_type: String,
+    pub initialized: bool,
+    pub sealed: bool,
+    /// Number of key shares required to unseal.
+    pub t: i64,
+    /// Total number of key shares.
+    pub n: i64,
+    /// Number of key shares provided so far while unsealing.
+    pub progress: i64,
+    pub version: String,
+    pub cluster_name: Option<String>,
+}
+
+/// A secrets engine mount, from `sys/internal/ui/mounts/<path>`.
+#[derive(Debug, Deserialize)]
+pub struct MountInfo {
+    #[serde(rename = "type")]
+    pub mount_type: String,
+    pub path: String,
+    pub options: Option<HashMap<String, String>>,
+}
+
+impl MountInfo {
+    /// The K/V version of the mount, if it is a K/V engine.
+    pub fn kv_version(&self) -> Option<u32> {
+        if self.mount_type != "kv" {
+            return None;
+        }
+        let version = self.options.as_ref().and_then(|o| o.get("version"));
+        Some(version.and_then(|v| v.parse().ok()).unwrap_or(1))
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct KvMetadata {
     pub created_time: String,
@@ -37,7 +137,7 @@ pub struct KvMetadata {
 
 #[derive(Debug, Deserialize)]
 pub struct KvData {
-    pub data: HashMap<String, String>,
+    pub data: HashMap<String, SecretString>,
     pub metadata: KvMetadata,
 }
 
@@ -50,3 +150,54 @@ pub struct KvKeys {
 pub struct VaultError {
     pub errors: Vec<String>,
 }
+
+#[derive(Debug, Deserialize)]
+pub struct TransitCiphertext {
+    pub ciphertext: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransitPlaintext {
+    /// Base64-encoded plaintext.
+    pub plaintext: SecretString,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DatabaseCredentials {
+    pub username: SecretString,
+    pub password: SecretString,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LeaseInfo {
+    pub id: String,
+    pub issue_time: String,
+    pub expire_time: Option<String>,
+    pub last_renewal: Option<String>,
+    pub renewable: bool,
+    /// Seconds remaining until the lease expires.
+    pub ttl: i64,
+}
+
+/// Parameters for issuing a certificate from a PKI engine.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CertificateRequest {
+    pub common_name: String,
+    /// Subject Alternative Names, DNS names or email addresses.
+    pub alt_names: Vec<String>,
+    /// Requested TTL (ex. `72h`), defaults to the role's TTL.
+    pub ttl: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PkiCertificate {
+    pub certificate: String,
+    pub issuing_ca: String,
+    /// The issuing CA and any intermediates up to the root, if the engine has a chain configured.
+    pub ca_chain: Option<Vec<String>>,
+    pub private_key: SecretString,
+    pub private_key_type: String,
+    pub serial_number: String,
+    /// Expiry as a Unix timestamp.
+    pub expiration: i64,
+}
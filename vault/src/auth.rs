@@ -1,13 +1,15 @@
 use crate::api::*;
 use crate::error::VaultClientError;
+use crate::secret::SecretString;
 use chrono::{DateTime, Duration, Utc};
 use failure::err_msg;
 use serde_json::{self, Value};
 use std::convert::From;
 
+#[derive(Debug)]
 pub struct Credentials {
     expires: Option<DateTime<Utc>>,
-    client_token: String,
+    client_token: SecretString,
 }
 
 impl Credentials {
@@ -30,10 +32,19 @@ impl From<AuthInfo> for Credentials {
     }
 }
 
+#[derive(Debug)]
 pub enum BackendType {
-    ClientToken(String),
-    GitHub(String),
-    AppRole { role_id: String, secret_id: String },
+    ClientToken(SecretString),
+    GitHub(SecretString),
+    AppRole {
+        role_id: String,
+        secret_id: SecretString,
+    },
+    /// An App Role whose secret_id has yet to be unwrapped from `wrapping_token`.
+    WrappedAppRole {
+        role_id: String,
+        wrapping_token: SecretString,
+    },
 }
 
 impl BackendType {
@@ -42,7 +53,7 @@ impl BackendType {
         match self {
             ClientToken(_) => "",
             GitHub(_) => "/v1/auth/github/login",
-            AppRole { .. } => "/v1/auth/approle/login",
+            AppRole { .. } | WrappedAppRole { .. } => "/v1/auth/approle/login",
         }
     }
 
@@ -52,11 +63,24 @@ impl BackendType {
             ClientToken(_) => Err(VaultClientError::InvalidPayload(err_msg(
                 "Can't log in with a client token",
             ))),
-            GitHub(t) => Ok(serde_json::to_value(GitHubToken::new(t.to_string()))?),
+            GitHub(t) => Ok(serde_json::to_value(GitHubToken::new(t.clone()))?),
             AppRole { role_id, secret_id } => Ok(serde_json::to_value(AppRoleToken::new(
                 role_id.as_str(),
-                secret_id.as_str(),
+                secret_id.clone(),
             ))?),
+            WrappedAppRole { .. } => Err(VaultClientError::InvalidPayload(err_msg(
+                "App Role secret_id must be unwrapped before logging in",
+            ))),
+        }
+    }
+
+    /// Name of the auth method, as used in its mount path.
+    pub fn method_name(&self) -> &str {
+        use BackendType::*;
+        match self {
+            ClientToken(_) => "token",
+            GitHub(_) => "github",
+            AppRole { .. } | WrappedAppRole { .. } => "approle",
         }
     }
 
@@ -65,32 +89,40 @@ impl BackendType {
         match self {
             ClientToken(_) => false,
             GitHub(_) => true,
-            AppRole { .. } => true,
+            AppRole { .. } | WrappedAppRole { .. } => true,
         }
     }
 }
 
+#[derive(Debug)]
 pub struct Backend {
     ty: BackendType,
     creds: Option<Credentials>,
 }
 
 impl Backend {
-    pub fn new_from_client_token<S: Into<String>>(token: S) -> Backend {
+    pub fn new_from_client_token<S: Into<SecretString>>(token: S) -> Backend {
+        let token = token.into();
         Backend {
-            ty: BackendType::ClientToken(token.into()),
-            creds: None,
+            creds: Some(Credentials {
+                expires: None,
+                client_token: token.clone(),
+            }),
+            ty: BackendType::ClientToken(token),
         }
     }
 
-    pub fn new_from_github_token<S: Into<String>>(token: S) -> Backend {
+    pub fn new_from_github_token<S: Into<SecretString>>(token: S) -> Backend {
         Backend {
             ty: BackendType::GitHub(token.into()),
             creds: None,
         }
     }
 
-    pub fn new_from_app_role<S: Into<String>>(role_id: S, secret_id: S) -> Backend {
+    pub fn new_from_app_role<R: Into<String>, S: Into<SecretString>>(
+        role_id: R,
+        secret_id: S,
+    ) -> Backend {
         Backend {
             ty: BackendType::AppRole {
                 role_id: role_id.into(),
@@ -100,6 +132,38 @@ impl Backend {
         }
     }
 
+    pub fn new_from_wrapped_app_role<R: Into<String>, S: Into<SecretString>>(
+        role_id: R,
+        wrapping_token: S,
+    ) -> Backend {
+        Backend {
+            ty: BackendType::WrappedAppRole {
+                role_id: role_id.into(),
+                wrapping_token: wrapping_token.into(),
+            },
+            creds: None,
+        }
+    }
+
+    /// The token wrapping the App Role secret_id, if it hasn't been unwrapped yet.
+    pub fn wrapping_token(&self) -> Option<&SecretString> {
+        match &self.ty {
+            BackendType::WrappedAppRole { wrapping_token, .. } => Some(wrapping_token),
+            _ => None,
+        }
+    }
+
+    /// Replace the wrapping token with the unwrapped App Role `secret_id`.  The wrapping token
+    /// is single-use, so the secret_id is kept for later logins.
+    pub fn set_unwrapped_secret_id(&mut self, secret_id: SecretString) {
+        if let BackendType::WrappedAppRole { role_id, .. } = &self.ty {
+            self.ty = BackendType::AppRole {
+                role_id: role_id.clone(),
+                secret_id,
+            };
+        }
+    }
+
     pub fn login_url(&self) -> &str {
         self.ty.login_url()
     }
@@ -108,6 +172,10 @@ impl Backend {
         self.ty.login_payload()
     }
 
+    pub fn method_name(&self) -> &str {
+        self.ty.method_name()
+    }
+
     pub fn can_expire(&self) -> bool {
         self.ty.can_expire()
     }
@@ -121,7 +189,9 @@ impl Backend {
     }
 
     pub fn client_token(&self) -> Option<&str> {
-        self.creds.as_ref().map(|c| c.client_token.as_str())
+        self.creds
+            .as_ref()
+            .map(|c| c.client_token.expose().as_str())
     }
 
     pub fn is_expired(&self) -> bool {
@@ -138,11 +208,11 @@ impl Backend {
 
 #[derive(Debug, Serialize)]
 pub struct GitHubToken {
-    token: String,
+    token: SecretString,
 }
 
 impl GitHubToken {
-    pub fn new<S: Into<String>>(token: S) -> GitHubToken {
+    pub fn new<S: Into<SecretString>>(token: S) -> GitHubToken {
         GitHubToken {
             token: token.into(),
         }
@@ -152,11 +222,11 @@ impl GitHubToken {
 #[derive(Debug, Serialize)]
 pub struct AppRoleToken {
     role_id: String,
-    secret_id: String,
+    secret_id: SecretString,
 }
 
 impl AppRoleToken {
-    pub fn new<S: Into<String>>(role_id: S, secret_id: S) -> AppRoleToken {
+    pub fn new<R: Into<String>, S: Into<SecretString>>(role_id: R, secret_id: S) -> AppRoleToken {
         AppRoleToken {
             role_id: role_id.into(),
             secret_id: secret_id.into(),
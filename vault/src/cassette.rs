@@ -0,0 +1,461 @@
+//! # Cassettes
+//!
+//! Record the HTTP interactions of a [`VaultClient`][client] to a file, and replay them later
+//! without a route to Vault.  See [`VaultClient::record_to`][record-to] and
+//! [`VaultClient::replay`][replay].
+//!
+//! Client tokens are never written to a cassette.  Secret values (anything under a response's
+//! `data`, apart from `LIST` key listings, the `metadata` of K/V version 2 reads and `sys/` server
+//! state) are handled according to the [`Redaction`][redaction] chosen when recording.
+//!
+//! [client]: ../client/struct.VaultClient.html
+//! [record-to]: ../client/struct.VaultClient.html#method.record_to
+//! [replay]: ../client/struct.VaultClient.html#method.replay
+//! [redaction]: ./enum.Redaction.html
+use crate::error::VaultClientError;
+use failure::err_msg;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, pbkdf2};
+use serde_json::Value;
+use std::fs;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+
+const REDACTED: &str = "REDACTED";
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// How secret values are stored in a cassette.
+pub enum Redaction {
+    /// Store secret values as-is.
+    Plaintext,
+    /// Replace secret values with `REDACTED`.  Replayed secrets keep their keys, but not their values.
+    Redact,
+    /// Encrypt secret values with a key derived from the given passphrase.  The same
+    /// passphrase must be supplied to replay the values.
+    Encrypt(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    pub path: String,
+    /// [`body_digest`](fn.body_digest.html) of the request body, if it had one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_digest: Option<String>,
+    pub status: u16,
+    pub body: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cassette {
+    pub vault_addr: String,
+    /// Base64 salt for the encryption key, present if secret values are encrypted.
+    pub salt: Option<String>,
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Cassette, VaultClientError> {
+        let contents = fs::read_to_string(path).map_err(|e| VaultClientError::Unknown(e.into()))?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), VaultClientError> {
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path, contents).map_err(|e| VaultClientError::Unknown(e.into()))
+    }
+}
+
+/// Appends interactions to a cassette file as they happen.
+pub struct Recorder {
+    path: PathBuf,
+    cassette: Cassette,
+    redaction: Redaction,
+    key: Option<LessSafeKey>,
+    rng: SystemRandom,
+}
+
+impl Recorder {
+    pub fn new(
+        path: PathBuf,
+        vault_addr: &str,
+        redaction: Redaction,
+    ) -> Result<Recorder, VaultClientError> {
+        let rng = SystemRandom::new();
+        let (salt, key) = match &redaction {
+            Redaction::Encrypt(passphrase) => {
+                let mut salt = [0; 16];
+                rng.fill(&mut salt)
+                    .map_err(|_| VaultClientError::Unknown(err_msg("Could not generate salt")))?;
+                (
+                    Some(base64::encode(&salt)),
+                    Some(derive_key(passphrase, &salt)),
+                )
+            }
+            _ => (None, None),
+        };
+        Ok(Recorder {
+            path,
+            cassette: Cassette {
+                vault_addr: vault_addr.to_string(),
+                salt,
+                interactions: Vec::new(),
+            },
+            redaction,
+            key,
+            rng,
+        })
+    }
+
+    /// Record an interaction and write the cassette to disk.
+    pub fn record(
+        &mut self,
+        method: &str,
+        path: &str,
+        request_digest: Option<&str>,
+        status: u16,
+        mut body: Value,
+    ) -> Result<(), VaultClientError> {
+        scrub_tokens(path, &mut body);
+        match self.redaction {
+            Redaction::Plaintext => {}
+            Redaction::Redact => {
+                map_secret_values(method, path, &mut body, &mut |_| Ok(REDACTED.to_string()))?
+            }
+            Redaction::Encrypt(_) => {
+                let key = self.key.as_ref().unwrap();
+                let rng = &self.rng;
+                map_secret_values(method, path, &mut body, &mut |s| encrypt(key, rng, s))?
+            }
+        }
+        self.cassette.interactions.push(Interaction {
+            method: method.to_string(),
+            path: path.to_string(),
+            request_digest: request_digest.map(str::to_string),
+            status,
+            body,
+        });
+        self.cassette.save(&self.path)
+    }
+}
+
+/// Serves recorded interactions in place of a Vault server.
+pub struct Player {
+    cassette: Cassette,
+    used: Vec<bool>,
+    key: Option<LessSafeKey>,
+}
+
+impl Player {
+    /// Load the cassette at `path`.  `passphrase` is required to replay encrypted values.
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        passphrase: Option<&str>,
+    ) -> Result<Player, VaultClientError> {
+        let cassette = Cassette::load(path)?;
+        let key = match (&cassette.salt, passphrase) {
+            (Some(salt), Some(passphrase)) => {
+                let salt =
+                    base64::decode(salt).map_err(|e| VaultClientError::InvalidPayload(e.into()))?;
+                Some(derive_key(passphrase, &salt))
+            }
+            _ => None,
+        };
+        let used = vec![false; cassette.interactions.len()];
+        Ok(Player {
+            cassette,
+            used,
+            key,
+        })
+    }
+
+    pub fn vault_addr(&self) -> &str {
+        &self.cassette.vault_addr
+    }
+
+    /// Find the response for a request, by its method, path and body digest.  Matching
+    /// interactions are served in recorded order, and the last one is repeated once they have
+    /// all been used.
+    pub fn play(
+        &mut self,
+        method: &str,
+        path: &str,
+        request_digest: Option<&str>,
+    ) -> Result<(u16, Value), VaultClientError> {
+        let matching: Vec<usize> = self
+            .cassette
+            .interactions
+            .iter()
+            .enumerate()
+            .filter(|(_, i)| {
+                i.method == method
+                    && i.path == path
+                    && i.request_digest.as_deref() == request_digest
+            })
+            .map(|(n, _)| n)
+            .collect();
+        let n = match matching
+            .iter()
+            .find(|n| !self.used[**n])
+            .or_else(|| matching.last())
+        {
+            Some(n) => *n,
+            None => {
+                return Err(VaultClientError::Unknown(err_msg(format!(
+                    "No recorded interaction for {} {}",
+                    method, path
+                ))))
+            }
+        };
+        self.used[n] = true;
+        let interaction = &self.cassette.interactions[n];
+        let mut body = interaction.body.clone();
+        if self.cassette.salt.is_some() {
+            let key = self.key.as_ref().ok_or_else(|| {
+                VaultClientError::InvalidPayload(err_msg(
+                    "Cassette is encrypted but no passphrase was given",
+                ))
+            })?;
+            map_secret_values(method, path, &mut body, &mut |s| decrypt(key, s))?;
+        }
+        Ok((interaction.status, body))
+    }
+}
+
+/// Identify a request body without storing it, since bodies may hold secrets (ex. plaintexts
+/// to encrypt with Transit).
+pub fn body_digest(body: &[u8]) -> String {
+    base64::encode(digest::digest(&digest::SHA256, body).as_ref())
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> LessSafeKey {
+    let mut key = [0; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, &key).unwrap())
+}
+
+fn encrypt(key: &LessSafeKey, rng: &SystemRandom, s: &str) -> Result<String, VaultClientError> {
+    let mut nonce = [0; NONCE_LEN];
+    rng.fill(&mut nonce)
+        .map_err(|_| VaultClientError::Unknown(err_msg("Could not generate nonce")))?;
+    let mut in_out = s.as_bytes().to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::empty(),
+        &mut in_out,
+    )
+    .map_err(|_| VaultClientError::Unknown(err_msg("Could not encrypt value")))?;
+    let mut out = nonce.to_vec();
+    out.extend(in_out);
+    Ok(format!("{}{}", ENCRYPTED_PREFIX, base64::encode(&out)))
+}
+
+fn decrypt(key: &LessSafeKey, s: &str) -> Result<String, VaultClientError> {
+    let encoded = match s.strip_prefix(ENCRYPTED_PREFIX) {
+        Some(e) => e,
+        None => return Ok(s.to_string()),
+    };
+    let invalid = || VaultClientError::InvalidPayload(err_msg("Could not decrypt cassette value"));
+    let mut bytes = base64::decode(encoded).map_err(|_| invalid())?;
+    if bytes.len() < NONCE_LEN {
+        return Err(invalid());
+    }
+    let mut in_out = bytes.split_off(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(&bytes).map_err(|_| invalid())?;
+    let plaintext = key
+        .open_in_place(nonce, Aad::empty(), &mut in_out)
+        .map_err(|_| invalid())?;
+    String::from_utf8(plaintext.to_vec()).map_err(|_| invalid())
+}
+
+/// Remove client tokens from login, wrapping and token lookup responses to `path`.
+fn scrub_tokens(path: &str, body: &mut Value) {
+    let mut fields = vec![
+        ("auth", "client_token"),
+        ("auth", "accessor"),
+        ("wrap_info", "token"),
+        ("wrap_info", "accessor"),
+    ];
+    if path.starts_with("/v1/auth/token/lookup") {
+        fields.extend(&[("data", "id"), ("data", "accessor")]);
+    }
+    for (section, field) in fields {
+        if let Some(v) = body.get_mut(section).and_then(|s| s.get_mut(field)) {
+            *v = Value::String(REDACTED.to_string());
+        }
+    }
+}
+
+/// Apply `f` to every string under the response `data` of a `method` request to `path`.
+/// `LIST` responses only hold key names and are left alone, as are `sys/` responses other than
+/// unwrapped secrets, and the `metadata` of K/V version 2 reads.
+fn map_secret_values<F>(
+    method: &str,
+    path: &str,
+    body: &mut Value,
+    f: &mut F,
+) -> Result<(), VaultClientError>
+where
+    F: FnMut(&str) -> Result<String, VaultClientError>,
+{
+    let server_state = path.starts_with("/v1/sys/") && !path.starts_with("/v1/sys/wrapping/unwrap");
+    if method == "LIST" || server_state {
+        return Ok(());
+    }
+    match body.get_mut("data") {
+        Some(data) if is_kv2_read(data) => map_strings(&mut data["data"], f),
+        Some(data) => map_strings(data, f),
+        None => Ok(()),
+    }
+}
+
+/// A K/V version 2 read has the secret in `data` and its version information in `metadata`.
+/// Version 1 secrets are flat, so any of their fields may share these names.
+fn is_kv2_read(data: &Value) -> bool {
+    let data = match data.as_object() {
+        Some(d) => d,
+        None => return false,
+    };
+    let metadata = data.get("metadata").and_then(Value::as_object);
+    data.len() == 2
+        && data
+            .get("data")
+            .is_some_and(|d| d.is_object() || d.is_null())
+        && metadata.is_some_and(|m| m.contains_key("version") && m.contains_key("created_time"))
+}
+
+fn map_strings<F>(val: &mut Value, f: &mut F) -> Result<(), VaultClientError>
+where
+    F: FnMut(&str) -> Result<String, VaultClientError>,
+{
+    match val {
+        Value::String(s) => *s = f(s)?,
+        Value::Array(a) => {
+            for v in a {
+                map_strings(v, f)?;
+            }
+        }
+        Value::Object(o) => {
+            for (_k, v) in o.iter_mut() {
+                map_strings(v, f)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn kv_response() -> Value {
+        json!({
+            "data": {
+                "data": { "password": "hunter2" },
+                "metadata": { "created_time": "2019-01-01T00:00:00Z", "version": 1 }
+            },
+            "auth": null
+        })
+    }
+
+    fn redact(method: &str, body: &mut Value) {
+        map_secret_values(method, "/v1/secret/data/app", body, &mut |_| {
+            Ok(REDACTED.to_string())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn redacts_secret_values() {
+        let mut body = kv_response();
+        redact("GET", &mut body);
+        assert_eq!(body["data"]["data"]["password"], REDACTED);
+        assert_eq!(
+            body["data"]["metadata"]["created_time"],
+            "2019-01-01T00:00:00Z"
+        );
+
+        let mut body = json!({ "data": { "keys": ["a", "b/"] } });
+        redact("LIST", &mut body);
+        assert_eq!(body["data"]["keys"], json!(["a", "b/"]));
+    }
+
+    #[test]
+    fn keeps_server_state() {
+        let mut body = json!({ "data": { "type": "kv", "options": { "version": "2" } } });
+        let original = body.clone();
+        map_secret_values(
+            "GET",
+            "/v1/sys/internal/ui/mounts/secret",
+            &mut body,
+            &mut |_| Ok(REDACTED.to_string()),
+        )
+        .unwrap();
+        assert_eq!(body, original);
+
+        let mut body = json!({ "data": { "password": "hunter2" } });
+        map_secret_values("POST", "/v1/sys/wrapping/unwrap", &mut body, &mut |_| {
+            Ok(REDACTED.to_string())
+        })
+        .unwrap();
+        assert_eq!(body["data"]["password"], REDACTED);
+    }
+
+    #[test]
+    fn redacts_kv1_fields_named_like_kv2_sections() {
+        let mut body = json!({
+            "data": {
+                "metadata": "hunter2",
+                "keys": ["hunter3"],
+                "data": { "nested": "hunter4" }
+            }
+        });
+        redact("GET", &mut body);
+        assert_eq!(
+            body["data"],
+            json!({
+                "metadata": REDACTED,
+                "keys": [REDACTED],
+                "data": { "nested": REDACTED }
+            })
+        );
+    }
+
+    #[test]
+    fn scrubs_client_tokens() {
+        let mut body = json!({ "auth": { "client_token": "s.abc", "policies": ["default"] } });
+        scrub_tokens("/v1/auth/approle/login", &mut body);
+        assert_eq!(body["auth"]["client_token"], REDACTED);
+        assert_eq!(body["auth"]["policies"], json!(["default"]));
+
+        let mut body = json!({ "data": { "id": "s.abc", "accessor": "xyz", "ttl": 60 } });
+        scrub_tokens("/v1/auth/token/lookup-self", &mut body);
+        assert_eq!(
+            body["data"],
+            json!({ "id": REDACTED, "accessor": REDACTED, "ttl": 60 })
+        );
+    }
+
+    #[test]
+    fn encrypted_values_round_trip() {
+        let key = derive_key("passphrase", b"salt");
+        let rng = SystemRandom::new();
+        let encrypted = encrypt(&key, &rng, "hunter2").unwrap();
+        assert!(encrypted.starts_with(ENCRYPTED_PREFIX));
+        assert!(!encrypted.contains("hunter2"));
+        assert_eq!(decrypt(&key, &encrypted).unwrap(), "hunter2");
+
+        let wrong_key = derive_key("wrong", b"salt");
+        assert!(decrypt(&wrong_key, &encrypted).is_err());
+    }
+}
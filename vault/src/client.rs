@@ -3,16 +3,23 @@
 //! An HTTP client to the Vault API that also contains an authentication backend instance that manages
 //! logging in to obtain client tokens and also refreshing client tokens, if possible.
 use crate::api::*;
-use failure::{bail, Error};
+use crate::cassette::{self, Player, Recorder, Redaction};
+use failure::{bail, err_msg, Error};
+use reqwest::header::{HeaderValue, CONTENT_TYPE};
 use reqwest::Client as HttpClient;
 use reqwest::Method;
+use reqwest::StatusCode;
 use reqwest::{Request, Url};
 use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 use crate::auth::Backend;
 use crate::error::VaultClientError;
+use crate::secret::{Secret, SecretString};
 use lazy_static::lazy_static;
 
 lazy_static! {
@@ -22,57 +29,97 @@ lazy_static! {
 pub struct VaultClient {
     client: HttpClient,
     vault_addr: Url,
+    /// All addresses the client may use, in order of preference.
+    addrs: Vec<Url>,
+    /// Addresses found unreachable, sealed or standby during this run.
+    unhealthy: Vec<Url>,
+    /// Whether `vault_addr` was picked from `addrs` by a health check.
+    addr_selected: bool,
     auth_backend: Backend,
+    recorder: Option<Recorder>,
+    player: Option<Player>,
 }
 
 impl VaultClient {
     /// Creates a `VaultClient` with a renewable login method that uses a github token for authentication.
-    pub fn github<S: Into<String>>(vault_addr: Url, github_token: S) -> VaultClient {
+    pub fn github<S: Into<SecretString>>(vault_addr: Url, github_token: S) -> VaultClient {
         VaultClient::new(vault_addr, Backend::new_from_github_token(github_token))
     }
 
     /// Creates a `VaultClient` with a non-renewable client token.
-    pub fn from_client_token<S: Into<String>>(vault_addr: Url, client_token: S) -> VaultClient {
+    pub fn from_client_token<S: Into<SecretString>>(
+        vault_addr: Url,
+        client_token: S,
+    ) -> VaultClient {
         VaultClient::new(vault_addr, Backend::new_from_client_token(client_token))
     }
 
     /// Creates a `VaultClient` with a renewable login method that uses an app
     /// role (role_id + secret_id) for authentication.
-    pub fn app_role<S: Into<String>>(vault_addr: Url, role_id: S, secret_id: S) -> VaultClient {
+    pub fn app_role<R: Into<String>, S: Into<SecretString>>(
+        vault_addr: Url,
+        role_id: R,
+        secret_id: S,
+    ) -> VaultClient {
         VaultClient::new(vault_addr, Backend::new_from_app_role(role_id, secret_id))
     }
 
+    /// Creates a `VaultClient` with a renewable login method that uses an app role, whose
+    /// secret_id is response-wrapped.  `wrapping_token` is unwrapped on first login, so the
+    /// secret_id is never handled by whoever delivers the token.
+    pub fn app_role_wrapped<R: Into<String>, S: Into<SecretString>>(
+        vault_addr: Url,
+        role_id: R,
+        wrapping_token: S,
+    ) -> VaultClient {
+        VaultClient::new(
+            vault_addr,
+            Backend::new_from_wrapped_app_role(role_id, wrapping_token),
+        )
+    }
+
     /// Creates a `VaultClient` based on environment vars.
     ///
-    /// `VAULT_ADDR` - **Required**. Specifies the base URL of the vault instance.
+    /// `VAULT_ADDR` - **Required**. Specifies the base URL of the vault instance, or a
+    /// comma-separated list of URLs to fail over between (see [`with_addrs`](#method.with_addrs)).
     ///
     /// Authentication methods:
     /// * Client Token - Specify the token with the `VAULT_TOKEN` env var.
     /// * Github Token - Specify the github token with the `VAULT_GITHUB_TOKEN` env var.
     /// * App Role - Specify the Role ID and Secret ID with the vars `VAULT_ROLE_TOKEN`
-    ///   and `VAULT_SECRET_TOKEN`, respectively.
+    ///   and `VAULT_SECRET_TOKEN`, respectively.  A response-wrapped Secret ID can be given
+    ///   with `VAULT_WRAPPED_SECRET_TOKEN` instead of `VAULT_SECRET_TOKEN`.
     ///
     /// Returns an `Err` result if the `VAULT_ADDR` is unspecified or an invalid URL, or
     /// if none of the authentication method vars are specified.
     pub fn from_env() -> Result<VaultClient, Error> {
         use std::env;
-        let vault_addr = env::var("VAULT_ADDR")?.parse()?;
-        if let Some(t) = env::var_os("VAULT_TOKEN") {
-            let token = t.to_string_lossy().to_owned();
-            Ok(VaultClient::from_client_token(vault_addr, token))
+        let addrs = parse_addrs(&env::var("VAULT_ADDR")?)?;
+        let vault_addr = addrs[0].clone();
+        let client = if let Some(t) = env::var_os("VAULT_TOKEN") {
+            let token = t.to_string_lossy().into_owned();
+            VaultClient::from_client_token(vault_addr, token)
         } else if let Some(t) = env::var_os("VAULT_GITHUB_TOKEN") {
-            let token = t.to_string_lossy().to_owned();
-            Ok(VaultClient::github(vault_addr, token))
+            let token = t.to_string_lossy().into_owned();
+            VaultClient::github(vault_addr, token)
         } else if let (Some(r), Some(s)) = (
             env::var_os("VAULT_ROLE_TOKEN"),
             env::var_os("VAULT_SECRET_TOKEN"),
         ) {
-            let role_id = r.to_string_lossy().to_owned();
-            let secret_id = s.to_string_lossy().to_owned();
-            Ok(VaultClient::app_role(vault_addr, role_id, secret_id))
+            let role_id = r.to_string_lossy().into_owned();
+            let secret_id = s.to_string_lossy().into_owned();
+            VaultClient::app_role(vault_addr, role_id, secret_id)
+        } else if let (Some(r), Some(w)) = (
+            env::var_os("VAULT_ROLE_TOKEN"),
+            env::var_os("VAULT_WRAPPED_SECRET_TOKEN"),
+        ) {
+            let role_id = r.to_string_lossy().into_owned();
+            let wrapping_token = w.to_string_lossy().into_owned();
+            VaultClient::app_role_wrapped(vault_addr, role_id, wrapping_token)
         } else {
             bail!("Could not find a token of a known type in environment")
-        }
+        };
+        Ok(client.with_addrs(addrs))
     }
 
     pub fn new(vault_addr: Url, auth_backend: Backend) -> VaultClient {
@@ -80,40 +127,489 @@ impl VaultClient {
 
         VaultClient {
             client,
+            addrs: vec![vault_addr.clone()],
+            unhealthy: Vec::new(),
+            addr_selected: false,
             vault_addr,
             auth_backend,
+            recorder: None,
+            player: None,
+        }
+    }
+
+    /// Creates a `VaultClient` that serves responses from a cassette previously recorded with
+    /// [`record_to`](#method.record_to), without contacting Vault.
+    ///
+    /// `passphrase` is required if the cassette was recorded with `Redaction::Encrypt`.
+    pub fn replay<P: AsRef<Path>>(
+        cassette: P,
+        passphrase: Option<&str>,
+    ) -> Result<VaultClient, VaultClientError> {
+        let player = Player::load(cassette, passphrase)?;
+        let vault_addr = player.vault_addr().parse()?;
+        let mut client = VaultClient::new(vault_addr, Backend::new_from_client_token(""));
+        client.player = Some(player);
+        Ok(client)
+    }
+
+    /// Record every request made by this client, and its response, to the cassette at `path`.
+    ///
+    /// Client tokens are never recorded, and secret values are stored according to `redaction`.
+    pub fn record_to<P: Into<PathBuf>>(
+        &mut self,
+        path: P,
+        redaction: Redaction,
+    ) -> Result<(), VaultClientError> {
+        self.recorder = Some(Recorder::new(
+            path.into(),
+            self.vault_addr.as_str(),
+            redaction,
+        )?);
+        Ok(())
+    }
+
+    /// Use any of `addrs`, in order of preference, instead of the single address the client
+    /// was created with.
+    ///
+    /// The first healthy (reachable, unsealed and active) node is picked on the first request
+    /// and used for the rest of the run.  If it later becomes sealed, requests fail over to the
+    /// next healthy node.  So do reads if it becomes unreachable, but not writes, which it may
+    /// have acted on before the connection failed.
+    pub fn with_addrs(mut self, addrs: Vec<Url>) -> VaultClient {
+        if let Some(first) = addrs.first() {
+            self.vault_addr = first.clone();
+            self.addrs = addrs;
+            self.unhealthy.clear();
+            self.addr_selected = false;
         }
+        self
     }
 
-    /// Base Vault URL
+    /// Base Vault URL, which is the node picked by a health check if the client has
+    /// several addresses.
     pub fn vault_addr(&self) -> &Url {
         &self.vault_addr
     }
 
+    /// All addresses the client may use, in order of preference.
+    pub fn vault_addrs(&self) -> &[Url] {
+        &self.addrs
+    }
+
+    /// Pick the first healthy address, if there is a choice to make and it hasn't been made yet.
+    fn select_addr(&mut self) -> Result<(), VaultClientError> {
+        let candidates: Vec<Url> = self
+            .addrs
+            .iter()
+            .filter(|a| !self.unhealthy.contains(a))
+            .cloned()
+            .collect();
+        if self.addr_selected || self.player.is_some() || candidates.len() < 2 {
+            return Ok(());
+        }
+        let mut problems = Vec::new();
+        for addr in candidates {
+            match self.probe(&addr) {
+                Ok(()) => {
+                    self.vault_addr = addr;
+                    self.addr_selected = true;
+                    return Ok(());
+                }
+                Err(problem) => {
+                    problems.push(format!("{} ({})", addr, problem));
+                    self.unhealthy.push(addr);
+                }
+            }
+        }
+        Err(VaultClientError::Unknown(err_msg(format!(
+            "No healthy vault server: {}",
+            problems.join(", ")
+        ))))
+    }
+
+    /// Check that the node at `addr` is reachable, unsealed and active.
+    fn probe(&self, addr: &Url) -> Result<(), String> {
+        let url = addr.join("/v1/sys/health").map_err(|e| e.to_string())?;
+        let resp = self.client.get(url).send().map_err(|e| e.to_string())?;
+        match resp.status().as_u16() {
+            // 473 is a performance standby, which serves reads itself
+            200 | 473 => Ok(()),
+            429 => Err("standby".to_string()),
+            501 => Err("not initialized".to_string()),
+            503 => Err("sealed".to_string()),
+            status => Err(format!("HTTP status {}", status)),
+        }
+    }
+
+    /// Stop using the current node, and pick the next healthy one.
+    fn fail_over(&mut self) -> Result<(), VaultClientError> {
+        self.unhealthy.push(self.vault_addr.clone());
+        self.addr_selected = false;
+        if let Some(next) = self.addrs.iter().find(|a| !self.unhealthy.contains(a)) {
+            self.vault_addr = next.clone();
+        }
+        self.select_addr()
+    }
+
+    /// Point a request built against any of the client's addresses at the current node.
+    fn rebase(&self, mut req: Request) -> Result<Request, VaultClientError> {
+        let url = req.url().as_str();
+        if url.starts_with(self.vault_addr.as_str()) {
+            return Ok(req);
+        }
+        if let Some(addr) = self.addrs.iter().find(|a| url.starts_with(a.as_str())) {
+            let rebased = format!("{}{}", self.vault_addr, &url[addr.as_str().len()..]);
+            *req.url_mut() = rebased.parse()?;
+        }
+        Ok(req)
+    }
+
     fn refresh_credentials(&mut self) -> Result<(), VaultClientError> {
         if !self.auth_backend.is_expired() {
             return Ok(());
         }
+        if let Some(wrapping_token) = self.auth_backend.wrapping_token().cloned() {
+            let resp: VaultResponse<AppRoleSecretId> =
+                self.unwrap_as(false, wrapping_token.expose())?;
+            let secret_id = expect_data(Some(resp))?.secret_id;
+            self.auth_backend.set_unwrapped_secret_id(secret_id);
+        }
+        // Logins aren't recorded, so their credentials never reach a cassette
         let url = self.vault_addr().join(self.auth_backend.login_url())?;
-        let req = self
-            .client
-            .post(url)
-            .json(&self.auth_backend.login_payload()?);
-        let resp: VaultResponse<()> = req.send()?.error_for_status()?.json()?;
-        self.auth_backend.set_credentials(resp.auth.unwrap().into());
+        let mut req = self.client.post(url).build()?;
+        set_json_body(&mut req, &self.auth_backend.login_payload()?)?;
+        let (url, status, body) = self.execute(req)?;
+        let resp: VaultResponse<()> = response_from_parts(status, &url, body)?;
+        let auth = resp.auth.ok_or_else(|| {
+            VaultClientError::InvalidPayload(err_msg("Login response did not contain auth"))
+        })?;
+        self.auth_backend.set_credentials(auth.into());
 
         Ok(())
     }
     /// Perform the HTTP request while first ensuring that we have valid credentials,
     /// and refresh them if needed.
-    fn request<P: DeserializeOwned>(&mut self, mut req: Request) -> Result<P, VaultClientError> {
-        self.refresh_credentials()?;
-        req.headers_mut().insert(
-            "X-Vault-Token",
-            self.auth_backend.client_token().unwrap().parse().unwrap(),
+    ///
+    /// Responses without a body (`204 No Content`) deserialize as JSON `null`.
+    fn request<P: DeserializeOwned>(
+        &mut self,
+        mut req: Request,
+        body: Option<&Value>,
+    ) -> Result<P, VaultClientError> {
+        if self.player.is_none() {
+            self.select_addr()?;
+            self.refresh_credentials()?;
+            req.headers_mut().insert(
+                "X-Vault-Token",
+                self.auth_backend.client_token().unwrap().parse().unwrap(),
+            );
+        }
+        self.send(req, body)
+    }
+
+    /// Perform the HTTP request as-is with the JSON `body`, recording or replaying it if
+    /// configured.  Requests are told apart by their method, path and body.
+    fn send<P: DeserializeOwned>(
+        &mut self,
+        mut req: Request,
+        body: Option<&Value>,
+    ) -> Result<P, VaultClientError> {
+        let digest = match body {
+            Some(body) => Some(set_json_body(&mut req, body)?),
+            None => None,
+        };
+        let method = req.method().to_string();
+        let path = request_path(req.url());
+        if let Some(player) = self.player.as_mut() {
+            let (status, body) = player.play(&method, &path, digest.as_deref())?;
+            return response_from_parts(status, req.url(), body);
+        }
+
+        let (url, status, body) = self.execute(req)?;
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(&method, &path, digest.as_deref(), status, body.clone())?;
+        }
+        response_from_parts(status, &url, body)
+    }
+
+    /// Perform the HTTP request against the current node, failing over to the next healthy
+    /// one if it's unavailable.  Returns the final URL, status and JSON body of the response.
+    fn execute(&mut self, req: Request) -> Result<(Url, u16, Value), VaultClientError> {
+        self.select_addr()?;
+        let mut req = self.rebase(req)?;
+        // A 503 from a sealed or standby node means the request wasn't handled, but a transport
+        // error may come after the node acted on it, so only reads are retried after one.
+        let idempotent = [Method::GET, Method::HEAD, LIST.clone()].contains(req.method());
+        let mut resp = loop {
+            let can_fail_over = self.addrs.len() - self.unhealthy.len() > 1;
+            let retry = if can_fail_over { req.try_clone() } else { None };
+            let result = self.client.execute(req);
+            let unavailable = match &result {
+                Ok(r) => r.status() == StatusCode::SERVICE_UNAVAILABLE,
+                Err(_) => idempotent,
+            };
+            match retry {
+                Some(retry) if unavailable => {
+                    self.fail_over()?;
+                    req = self.rebase(retry)?;
+                }
+                _ => break result?,
+            }
+        };
+        let url = resp.url().clone();
+        let status = resp.status().as_u16();
+        let text = resp.text()?;
+        let body = match serde_json::from_str(&text) {
+            Ok(body) => body,
+            Err(_) if text.trim().is_empty() || status >= 400 => Value::Null,
+            Err(e) => return Err(e.into()),
+        };
+        Ok((url, status, body))
+    }
+
+    /// URL for a logical `path` (ex. `secret/data/apps/my-app`).
+    fn logical_url(&self, path: &str) -> Result<Url, VaultClientError> {
+        Ok(self
+            .vault_addr()
+            .join("/v1/")?
+            .join(&strip_leading_slash(path))?)
+    }
+
+    fn read_as<D: DeserializeOwned>(
+        &mut self,
+        path: &str,
+    ) -> Result<VaultResponse<D>, VaultClientError> {
+        let req = self.client.get(self.logical_url(path)?).build()?;
+        self.request(req, None)
+    }
+
+    fn list_as<D: DeserializeOwned>(
+        &mut self,
+        path: &str,
+    ) -> Result<VaultResponse<D>, VaultClientError> {
+        let mut req = self.client.get(self.logical_url(path)?).build()?;
+        *req.method_mut() = LIST.clone();
+        self.request(req, None)
+    }
+
+    fn write_as<D: DeserializeOwned, B: Serialize>(
+        &mut self,
+        path: &str,
+        body: &B,
+    ) -> Result<Option<VaultResponse<D>>, VaultClientError> {
+        let req = self.client.post(self.logical_url(path)?).build()?;
+        self.request(req, Some(&serde_json::to_value(body)?))
+    }
+
+    /// Unwrap the response wrapped by `wrapping_token`.  The request is authenticated with the
+    /// client's token if `with_client_token` is set, and otherwise with the wrapping token
+    /// itself, which lets it be used before logging in.
+    fn unwrap_as<D: DeserializeOwned>(
+        &mut self,
+        with_client_token: bool,
+        wrapping_token: &str,
+    ) -> Result<VaultResponse<D>, VaultClientError> {
+        let url = self.logical_url("sys/wrapping/unwrap")?;
+        if with_client_token {
+            let body = json!({ "token": wrapping_token });
+            let req = self.client.post(url).build()?;
+            self.request(req, Some(&body))
+        } else {
+            let req = self
+                .client
+                .post(url)
+                .header("X-Vault-Token", wrapping_token)
+                .build()?;
+            self.send(req, None)
+        }
+    }
+
+    /// Read the logical `path`, for any secrets engine or system backend (ex. `sys/mounts`).
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn read<S: AsRef<str>>(
+        &mut self,
+        path: S,
+    ) -> Result<VaultResponse<Value>, VaultClientError> {
+        self.read_as(path.as_ref())
+    }
+
+    /// Get the health of the Vault node, without authenticating.
+    ///
+    /// A sealed, uninitialized or standby node is reported in the status rather than as an error.
+    pub fn health(&mut self) -> Result<HealthStatus, VaultClientError> {
+        let mut url = self.logical_url("sys/health")?;
+        url.set_query(Some(
+            "standbyok=true&perfstandbyok=true&sealedcode=200&uninitcode=200",
+        ));
+        let req = self.client.get(url).build()?;
+        self.send(req, None)
+    }
+
+    /// Get the seal status of the Vault node, without authenticating.
+    pub fn seal_status(&mut self) -> Result<SealStatus, VaultClientError> {
+        let req = self
+            .client
+            .get(self.logical_url("sys/seal-status")?)
+            .build()?;
+        self.send(req, None)
+    }
+
+    /// Look up the type and options (such as the K/V version) of the secrets engine mounted at `path`.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn mount_info<S: AsRef<str>>(&mut self, path: S) -> Result<MountInfo, VaultClientError> {
+        let path = format!(
+            "sys/internal/ui/mounts/{}",
+            strip_leading_slash(path.as_ref())
         );
+        let resp: VaultResponse<MountInfo> = self.read_as(&path)?;
+        expect_data(Some(resp))
+    }
 
-        Ok(self.client.execute(req)?.error_for_status()?.json()?)
+    /// Look up the properties of the client token: its policies, identity and remaining TTL.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn lookup_self(&mut self) -> Result<TokenInfo, VaultClientError> {
+        let resp: VaultResponse<TokenInfo> = self.read_as("auth/token/lookup-self")?;
+        expect_data(Some(resp))
+    }
+
+    /// The capabilities (ex. `read`, `list`, `deny`) the client token has on each of
+    /// the logical `paths`, according to its policies.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn capabilities_self<S: AsRef<str>>(
+        &mut self,
+        paths: &[S],
+    ) -> Result<HashMap<String, Vec<String>>, VaultClientError> {
+        let paths: Vec<String> = paths
+            .iter()
+            .map(|p| strip_leading_slash(p.as_ref()).into_owned())
+            .collect();
+        let body = json!({ "paths": paths });
+        let resp: Option<VaultResponse<HashMap<String, Value>>> =
+            self.write_as("sys/capabilities-self", &body)?;
+        let mut data = expect_data(resp)?;
+        let mut capabilities = HashMap::new();
+        for path in paths {
+            let caps = data
+                .remove(&path)
+                .and_then(|c| serde_json::from_value(c).ok())
+                .unwrap_or_default();
+            capabilities.insert(path, caps);
+        }
+        Ok(capabilities)
+    }
+
+    /// Name of the auth method used to obtain the client token (ex. `approle`).
+    pub fn auth_method(&self) -> &str {
+        self.auth_backend.method_name()
+    }
+
+    /// Read the logical `path` with the response wrapped in a single-use token valid for
+    /// `wrap_ttl` (ex. `5m`), so that the secret can be handed to another party to unwrap.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn read_wrapped<S: AsRef<str>>(
+        &mut self,
+        path: S,
+        wrap_ttl: &str,
+    ) -> Result<WrapInfo, VaultClientError> {
+        let req = self
+            .client
+            .get(self.logical_url(path.as_ref())?)
+            .header("X-Vault-Wrap-TTL", wrap_ttl)
+            .build()?;
+        let resp: VaultResponse<Value> = self.request(req, None)?;
+        resp.wrap_info.ok_or_else(|| {
+            VaultClientError::InvalidPayload(failure::err_msg("Response was not wrapped"))
+        })
+    }
+
+    /// Unwrap the response wrapped by `wrapping_token`, which can only be done once.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn unwrap_response<S: AsRef<str>>(
+        &mut self,
+        wrapping_token: S,
+    ) -> Result<VaultResponse<Value>, VaultClientError> {
+        self.refresh_credentials()?;
+        let with_client_token = self.auth_backend.client_token().is_some();
+        self.unwrap_as(with_client_token, wrapping_token.as_ref())
+    }
+
+    /// Look up the creation path, time and TTL of `wrapping_token`, without unwrapping it.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn lookup_wrapping_token<S: AsRef<str>>(
+        &mut self,
+        wrapping_token: S,
+    ) -> Result<WrappingLookup, VaultClientError> {
+        let body = json!({ "token": wrapping_token.as_ref() });
+        let resp: Option<VaultResponse<WrappingLookup>> =
+            self.write_as("sys/wrapping/lookup", &body)?;
+        expect_data(resp)
+    }
+
+    /// Exchange `wrapping_token` for a new one wrapping the same response, extending its TTL.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn rewrap<S: AsRef<str>>(
+        &mut self,
+        wrapping_token: S,
+    ) -> Result<WrapInfo, VaultClientError> {
+        let body = json!({ "token": wrapping_token.as_ref() });
+        let resp: Option<VaultResponse<Value>> = self.write_as("sys/wrapping/rewrap", &body)?;
+        resp.and_then(|r| r.wrap_info).ok_or_else(|| {
+            VaultClientError::InvalidPayload(failure::err_msg("Response was not wrapped"))
+        })
+    }
+
+    /// List the keys under the logical `path`.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn list<S: AsRef<str>>(
+        &mut self,
+        path: S,
+    ) -> Result<VaultResponse<Value>, VaultClientError> {
+        self.list_as(path.as_ref())
+    }
+
+    /// Write `data` to the logical `path`.  Returns `None` if Vault responded without a body.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn write<S: AsRef<str>, D: Serialize>(
+        &mut self,
+        path: S,
+        data: &D,
+    ) -> Result<Option<VaultResponse<Value>>, VaultClientError> {
+        self.write_as(path.as_ref(), data)
+    }
+
+    /// Delete the logical `path`.  Returns `None` if Vault responded without a body.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn delete<S: AsRef<str>>(
+        &mut self,
+        path: S,
+    ) -> Result<Option<VaultResponse<Value>>, VaultClientError> {
+        let req = self
+            .client
+            .delete(self.logical_url(path.as_ref())?)
+            .build()?;
+        self.request(req, None)
     }
 
     /// Get the KV secret from the specified `engine` and the specified `path`.
@@ -124,12 +620,13 @@ impl VaultClient {
         &mut self,
         engine: S,
         path: S,
-    ) -> Result<HashMap<String, String>, VaultClientError> {
-        let engine_path = format!("/v1/{}/data/", engine.as_ref());
-        let secret_path = strip_leading_slash(path.as_ref());
-        let url = self.vault_addr().join(&engine_path)?.join(&secret_path)?;
-        let req = self.client.get(url).build()?;
-        let resp: VaultResponse<KvData> = self.request(req)?;
+    ) -> Result<HashMap<String, SecretString>, VaultClientError> {
+        let path = format!(
+            "{}/data/{}",
+            engine.as_ref(),
+            strip_leading_slash(path.as_ref())
+        );
+        let resp: VaultResponse<KvData> = self.read_as(&path)?;
         Ok(resp.data.unwrap().data)
     }
 
@@ -142,17 +639,215 @@ impl VaultClient {
         engine: S,
         path: S,
     ) -> Result<Vec<String>, VaultClientError> {
-        let engine_path = format!("/v1/{}/metadata/", engine.as_ref());
-        let secret_path = strip_leading_slash(path.as_ref());
-        let url = self.vault_addr().join(&engine_path)?.join(&secret_path)?;
-        let mut req = self.client.get(url).build()?;
-        *req.method_mut() = LIST.clone();
-        let resp: VaultResponse<KvKeys> = self.request(req)?;
+        let path = format!(
+            "{}/metadata/{}",
+            engine.as_ref(),
+            strip_leading_slash(path.as_ref())
+        );
+        let resp: VaultResponse<KvKeys> = self.list_as(&path)?;
+        Ok(resp.data.unwrap().keys)
+    }
+
+    /// Encrypt `plaintext` with the named `key` of the Transit `engine`, returning
+    /// the ciphertext (ex. `vault:v1:...`).
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn transit_encrypt<S: AsRef<str>>(
+        &mut self,
+        engine: S,
+        key: S,
+        plaintext: &[u8],
+    ) -> Result<String, VaultClientError> {
+        let path = format!("{}/encrypt/{}", engine.as_ref(), key.as_ref());
+        let body = json!({ "plaintext": base64::encode(plaintext) });
+        let resp: Option<VaultResponse<TransitCiphertext>> = self.write_as(&path, &body)?;
+        Ok(expect_data(resp)?.ciphertext)
+    }
+
+    /// Decrypt `ciphertext` with the named `key` of the Transit `engine`.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn transit_decrypt<S: AsRef<str>>(
+        &mut self,
+        engine: S,
+        key: S,
+        ciphertext: S,
+    ) -> Result<Secret<Vec<u8>>, VaultClientError> {
+        let path = format!("{}/decrypt/{}", engine.as_ref(), key.as_ref());
+        let body = json!({ "ciphertext": ciphertext.as_ref() });
+        let resp: Option<VaultResponse<TransitPlaintext>> = self.write_as(&path, &body)?;
+        let plaintext = expect_data(resp)?.plaintext;
+        base64::decode(plaintext.expose())
+            .map(Secret::new)
+            .map_err(|e| VaultClientError::InvalidPayload(e.into()))
+    }
+
+    /// Re-encrypt `ciphertext` with the latest version of the named `key` of the Transit `engine`,
+    /// without exposing the plaintext.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn transit_rewrap<S: AsRef<str>>(
+        &mut self,
+        engine: S,
+        key: S,
+        ciphertext: S,
+    ) -> Result<String, VaultClientError> {
+        let path = format!("{}/rewrap/{}", engine.as_ref(), key.as_ref());
+        let body = json!({ "ciphertext": ciphertext.as_ref() });
+        let resp: Option<VaultResponse<TransitCiphertext>> = self.write_as(&path, &body)?;
+        Ok(expect_data(resp)?.ciphertext)
+    }
+
+    /// Generate credentials for the named `role` of the database `engine`.
+    ///
+    /// The credentials are only valid for the response's `lease_duration` (in seconds),
+    /// unless the lease (`lease_id`) is renewed.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn get_database_credentials<S: AsRef<str>>(
+        &mut self,
+        engine: S,
+        role: S,
+    ) -> Result<VaultResponse<DatabaseCredentials>, VaultClientError> {
+        let path = format!("{}/creds/{}", engine.as_ref(), role.as_ref());
+        self.read_as(&path)
+    }
+
+    /// Issue a certificate and private key from the named `role` of the PKI `engine`.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn issue_certificate<S: AsRef<str>>(
+        &mut self,
+        engine: S,
+        role: S,
+        request: &CertificateRequest,
+    ) -> Result<PkiCertificate, VaultClientError> {
+        let path = format!("{}/issue/{}", engine.as_ref(), role.as_ref());
+        let mut body = json!({ "common_name": request.common_name });
+        if !request.alt_names.is_empty() {
+            body["alt_names"] = json!(request.alt_names.join(","));
+        }
+        if let Some(ttl) = &request.ttl {
+            body["ttl"] = json!(ttl);
+        }
+        let resp: Option<VaultResponse<PkiCertificate>> = self.write_as(&path, &body)?;
+        expect_data(resp)
+    }
+
+    /// Renew the lease `lease_id`, optionally requesting a new TTL of `increment` seconds.
+    /// Vault may grant a shorter TTL than requested; the response's `lease_duration` is the one granted.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn renew_lease<S: AsRef<str>>(
+        &mut self,
+        lease_id: S,
+        increment: Option<i64>,
+    ) -> Result<VaultResponse<Value>, VaultClientError> {
+        let mut body = json!({ "lease_id": lease_id.as_ref() });
+        if let Some(increment) = increment {
+            body["increment"] = json!(increment);
+        }
+        let resp = self.write_as("sys/leases/renew", &body)?;
+        resp.ok_or_else(|| {
+            VaultClientError::InvalidPayload(failure::err_msg("Renewal response was empty"))
+        })
+    }
+
+    /// Revoke the lease `lease_id`, invalidating the secret it was issued with.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn revoke_lease<S: AsRef<str>>(&mut self, lease_id: S) -> Result<(), VaultClientError> {
+        let body = json!({ "lease_id": lease_id.as_ref() });
+        let _: Option<VaultResponse<Value>> = self.write_as("sys/leases/revoke", &body)?;
+        Ok(())
+    }
+
+    /// Look up the issue time, expiry and TTL of the lease `lease_id`.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn lookup_lease<S: AsRef<str>>(
+        &mut self,
+        lease_id: S,
+    ) -> Result<LeaseInfo, VaultClientError> {
+        let body = json!({ "lease_id": lease_id.as_ref() });
+        let resp: Option<VaultResponse<LeaseInfo>> = self.write_as("sys/leases/lookup", &body)?;
+        expect_data(resp)
+    }
+
+    /// List the names of the keys in the Transit `engine`.
+    ///
+    /// Will perform a login if using an appropriate authentication
+    /// method and no currently-valid client token.
+    pub fn list_transit_keys<S: AsRef<str>>(
+        &mut self,
+        engine: S,
+    ) -> Result<Vec<String>, VaultClientError> {
+        let path = format!("{}/keys", engine.as_ref());
+        let resp: VaultResponse<KvKeys> = self.list_as(&path)?;
         Ok(resp.data.unwrap().keys)
     }
 }
 
-fn strip_leading_slash(p: &str) -> Cow<str> {
+/// The `data` of a response that is expected to have one.
+fn expect_data<D>(resp: Option<VaultResponse<D>>) -> Result<D, VaultClientError> {
+    resp.and_then(|r| r.data).ok_or_else(|| {
+        VaultClientError::InvalidPayload(failure::err_msg("Response did not contain any data"))
+    })
+}
+
+/// Parse a comma-separated list of vault addresses.
+fn parse_addrs(addrs: &str) -> Result<Vec<Url>, Error> {
+    let addrs = addrs
+        .split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(|a| a.parse())
+        .collect::<Result<Vec<Url>, _>>()?;
+    if addrs.is_empty() {
+        bail!("VAULT_ADDR does not contain any addresses");
+    }
+    Ok(addrs)
+}
+
+/// Path and query of `url`, which identifies a request independently of the Vault address.
+fn request_path(url: &Url) -> String {
+    match url.query() {
+        Some(q) => format!("{}?{}", url.path(), q),
+        None => url.path().to_string(),
+    }
+}
+
+/// Set `body` as the JSON body of `req`, returning its digest.
+fn set_json_body(req: &mut Request, body: &Value) -> Result<String, VaultClientError> {
+    let bytes = serde_json::to_vec(body)?;
+    let digest = cassette::body_digest(&bytes);
+    req.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    *req.body_mut() = Some(bytes.into());
+    Ok(digest)
+}
+
+/// Turn an error `status` into a `VaultClientError`, otherwise deserialize `body`.
+fn response_from_parts<P: DeserializeOwned>(
+    status: u16,
+    url: &Url,
+    body: Value,
+) -> Result<P, VaultClientError> {
+    if status >= 400 {
+        return Err(VaultClientError::from_status(status, url.as_str(), &body));
+    }
+    Ok(serde_json::from_value(body)?)
+}
+
+fn strip_leading_slash(p: &str) -> Cow<'_, str> {
     if p.starts_with('/') {
         Cow::Owned(p.chars().skip_while(|c| *c == '/').collect())
     } else {
@@ -162,6 +857,18 @@ fn strip_leading_slash(p: &str) -> Cow<str> {
 
 #[cfg(test)]
 mod test {
+    #[test]
+    fn parses_comma_separated_addrs() {
+        use super::parse_addrs;
+        let addrs =
+            parse_addrs("https://vault-a.example.com, https://vault-b.example.com:8200,").unwrap();
+        assert_eq!(addrs.len(), 2);
+        assert_eq!(addrs[0].as_str(), "https://vault-a.example.com/");
+        assert_eq!(addrs[1].as_str(), "https://vault-b.example.com:8200/");
+        assert!(parse_addrs(" , ").is_err());
+        assert!(parse_addrs("https://vault-a.example.com,not a url").is_err());
+    }
+
     #[test]
     fn test_strip_leading_slash() {
         use super::strip_leading_slash;
@@ -1,24 +1,57 @@
-use failure::{Error, Fail};
+use failure::{err_msg, Error, Fail};
 use reqwest::StatusCode;
 use reqwest::{Error as HttpError, UrlError};
-use serde_json::Error as JsonError;
+use serde_json::{Error as JsonError, Value};
 use std::convert::From;
+use std::fmt;
 use std::string::ToString;
 
-#[derive(Debug, Fail)]
+#[derive(Debug)]
 pub enum VaultClientError {
-    #[fail(display = "Not Found: {}", _0)]
     NotFound(String),
-    #[fail(display = "Not Authorized: {}", _0)]
     NotAuthorized(Error),
-    #[fail(display = "Invalid Url: {}", _0)]
     InvalidUrl(Error),
-    #[fail(display = "Invalid Payload: {}", _0)]
     InvalidPayload(Error),
-    #[fail(display = "Unknown Client error: {}", _0)]
     Unknown(Error),
 }
 
+impl fmt::Display for VaultClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use VaultClientError::*;
+        match self {
+            NotFound(s) => write!(f, "Not Found: {}", s),
+            NotAuthorized(e) => write!(f, "Not Authorized: {}", e),
+            InvalidUrl(e) => write!(f, "Invalid Url: {}", e),
+            InvalidPayload(e) => write!(f, "Invalid Payload: {}", e),
+            Unknown(e) => write!(f, "Unknown Client error: {}", e),
+        }
+    }
+}
+
+impl Fail for VaultClientError {}
+
+impl VaultClientError {
+    /// Build the error for an HTTP error `status` received from `url`, using
+    /// the messages in the Vault error `body` if present.
+    pub fn from_status(status: u16, url: &str, body: &Value) -> VaultClientError {
+        let errors = body["errors"]
+            .as_array()
+            .map(|e| {
+                e.iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            })
+            .unwrap_or_default();
+        let msg = || err_msg(format!("HTTP status {} for {}: {}", status, url, errors));
+        match status {
+            401 | 403 => VaultClientError::NotAuthorized(msg()),
+            404 => VaultClientError::NotFound(url.to_string()),
+            _ => VaultClientError::Unknown(msg()),
+        }
+    }
+}
+
 impl From<UrlError> for VaultClientError {
     fn from(e: UrlError) -> VaultClientError {
         VaultClientError::InvalidUrl(e.into())
@@ -38,7 +71,7 @@ impl From<HttpError> for VaultClientError {
                 VaultClientError::NotAuthorized(e.into())
             }
             Some(StatusCode::NOT_FOUND) => {
-                let url = e.url().map(ToString::to_string).unwrap_or_else(String::new);
+                let url = e.url().map(ToString::to_string).unwrap_or_default();
                 VaultClientError::NotFound(url)
             }
             _ => VaultClientError::Unknown(e.into()),
@@ -7,28 +7,82 @@
 //! * [Client Token][client-token] - Provide a token that you've already obtained by logging in through other means.
 //! * [Github Token][github-token] - Provide a github token that will be used to log in and obtain the client token.
 //! * [App Role][app-role] - Provide a Role and Secret ID to use to log in to obtain the client token.
+//!   The Secret ID can also be [response-wrapped][app-role-wrapped], and is unwrapped before the first login.
 //!
 //! The above methods can also source from the environment, see the [`from_env`][from-env] method.
 //!
+//! ## High Availability
+//!
+//! A client can be given several addresses with [`with_addrs`][client-with-addrs] (or a comma-separated
+//! `VAULT_ADDR`).  It uses the first healthy node, and fails over to the next one if that node becomes
+//! unreachable or sealed.  [`health`][client-health] and [`seal_status`][client-seal-status] report on the node in use.
+//!
 //! ## Available Secrets Engines
 //!
-//! Currently only K/V version 2 is supported.  This can be easily extended via adding methods to the [`VaultClient`][client].  Currently supports getting secrets for a path via [`get_kv_secret`][client-get-kv-secret] and listing secrets on a path via [`list_kv_keys`][client-list-kv-keys].
+//! K/V version 2 has typed helpers on the [`VaultClient`][client].  Currently supports getting secrets for a path via [`get_kv_secret`][client-get-kv-secret] and listing secrets on a path via [`list_kv_keys`][client-list-kv-keys].
+//!
+//! There are also typed helpers for [Transit][client-transit-encrypt], [database credentials][client-get-database-credentials],
+//! [PKI certificates][client-issue-certificate] and [leases][client-renew-lease], and any read can be
+//! [response-wrapped][client-read-wrapped] for hand-off to another party.
+//!
+//! Any other engine can be used through the generic logical API, [`read`][client-read], [`list`][client-list],
+//! [`write`][client-write] and [`delete`][client-delete], which return the raw response data as JSON along with lease info and warnings.
+//!
+//! ## Secrets
+//!
+//! Client tokens and secret values are wrapped in [`Secret`][secret], which is redacted in
+//! `Debug` output and zeroed on drop.  Use [`expose`][secret-expose] to access the value.
+//!
+//! ## Recording and Replay
+//!
+//! A client can record its requests and responses to a cassette file with
+//! [`record_to`][client-record-to], and a client created with [`replay`][client-replay]
+//! serves them back without a route to Vault.  See the [`cassette`][cassette] module.
+//!
+//! ## Testing
+//!
+//! Enabling the `test-util` feature provides [`FakeVault`][fake-vault], an in-process fake Vault
+//! server for testing code built on [`VaultClient`][client] over real HTTP.
 //!
 //! [client]: ./client/struct.VaultClient.html
+//! [client-record-to]: ./client/struct.VaultClient.html#method.record_to
+//! [client-replay]: ./client/struct.VaultClient.html#method.replay
+//! [cassette]: ./cassette/index.html
+//! [secret]: ./secret/struct.Secret.html
+//! [secret-expose]: ./secret/struct.Secret.html#method.expose
+//! [fake-vault]: ./test_util/struct.FakeVault.html
 //! [auth-backend]: ./auth/struct.Backend.html
 //! [client-token]: ./client/struct.VaultClient.html#method.from_client_token
 //! [github-token]: ./client/struct.VaultClient.html#method.github
 //! [app-role]: ./client/struct.VaultClient.html#method.app_role
+//! [app-role-wrapped]: ./client/struct.VaultClient.html#method.app_role_wrapped
 //! [from-env]: ./client/struct.VaultClient.html#method.from_env
+//! [client-with-addrs]: ./client/struct.VaultClient.html#method.with_addrs
+//! [client-health]: ./client/struct.VaultClient.html#method.health
+//! [client-seal-status]: ./client/struct.VaultClient.html#method.seal_status
 //! [client-get-kv-secret]: ./client/struct.VaultClient.html#method.get_kv_secret
 //! [client-list-kv-keys]: ./client/struct.VaultClient.html#method.list_kv_keys
+//! [client-transit-encrypt]: ./client/struct.VaultClient.html#method.transit_encrypt
+//! [client-get-database-credentials]: ./client/struct.VaultClient.html#method.get_database_credentials
+//! [client-issue-certificate]: ./client/struct.VaultClient.html#method.issue_certificate
+//! [client-renew-lease]: ./client/struct.VaultClient.html#method.renew_lease
+//! [client-read-wrapped]: ./client/struct.VaultClient.html#method.read_wrapped
+//! [client-read]: ./client/struct.VaultClient.html#method.read
+//! [client-list]: ./client/struct.VaultClient.html#method.list
+//! [client-write]: ./client/struct.VaultClient.html#method.write
+//! [client-delete]: ./client/struct.VaultClient.html#method.delete
 #[macro_use]
 extern crate serde_derive;
 
 pub mod api;
 pub mod auth;
+pub mod cassette;
 pub mod client;
 pub mod error;
+pub mod secret;
+#[cfg(feature = "test-util")]
+pub mod test_util;
 
 pub use client::VaultClient;
 pub use error::VaultClientError;
+pub use secret::SecretString;
@@ -0,0 +1,1219 @@
+//! # Test Utilities
+//!
+//! Available with the `test-util` feature.  [`FakeVault`][fake-vault] is an in-process HTTP server
+//! that speaks enough of the Vault API to exercise a [`VaultClient`][client] end-to-end without
+//! a Vault install:
+//!
+//! * K/V version 1 and version 2 engines, including `LIST` and `?list=true`
+//! * Transit encrypt, decrypt, rewrap and key listing, with a reversible fake cipher
+//! * Database engine credentials, issued with a lease
+//! * Lease renewal, revocation and lookup
+//! * PKI certificate issuing, with fake PEM blocks
+//! * Response wrapping (`X-Vault-Wrap-TTL`), unwrap, lookup and rewrap
+//! * Client token, GitHub and App Role login, and token lookup-self
+//! * `sys/health`, `sys/seal-status` and mount lookups, with configurable seal and standby state
+//! * Configurable `sys/capabilities-self` results (capabilities are reported, not enforced)
+//! * Configurable token TTLs and injected failures
+//!
+//! ## Examples
+//!
+//! ```rust
+//! use vault::test_util::FakeVault;
+//!
+//! let vault = FakeVault::start();
+//! vault.mount_kv2("secret");
+//! vault.put_secret("secret", "apps/my-app", &[("password", "hunter2")]);
+//!
+//! let mut client = vault.client();
+//! let secret = client.get_kv_secret("secret", "apps/my-app").unwrap();
+//! assert_eq!(secret["password"].expose(), "hunter2");
+//! ```
+//!
+//! [fake-vault]: ./struct.FakeVault.html
+//! [client]: ../client/struct.VaultClient.html
+use crate::client::VaultClient;
+use reqwest::Url;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const FAKE_VERSION: &str = "1.2.3";
+const FAKE_CLUSTER_NAME: &str = "fake-vault";
+
+/// The token accepted by a fresh [`FakeVault`](struct.FakeVault.html), with no expiry.
+pub const ROOT_TOKEN: &str = "root";
+
+/// A request received by the [`FakeVault`](struct.FakeVault.html).
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub token: Option<String>,
+    pub body: Option<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum KvVersion {
+    V1,
+    V2,
+}
+
+struct KvSecret {
+    data: Map<String, Value>,
+    version: i64,
+}
+
+struct KvMount {
+    version: KvVersion,
+    secrets: BTreeMap<String, KvSecret>,
+}
+
+struct TransitMount {
+    keys: BTreeMap<String, i64>,
+}
+
+struct DatabaseMount {
+    /// Role name to credential TTL in seconds.
+    roles: BTreeMap<String, i64>,
+}
+
+struct FakeLease {
+    ttl: i64,
+    expires: Instant,
+}
+
+struct WrappedResponse {
+    body: Value,
+    creation_path: String,
+    ttl: i64,
+}
+
+struct FakeToken {
+    expires: Option<Instant>,
+    ttl: i64,
+    policies: Vec<String>,
+    display_name: String,
+    entity_id: String,
+    path: String,
+}
+
+impl FakeToken {
+    /// A token that never expires, as if created with `auth/token/create`.
+    fn new(display_name: &str, policies: Vec<String>, path: &str) -> FakeToken {
+        FakeToken {
+            expires: None,
+            ttl: 0,
+            policies,
+            display_name: display_name.to_string(),
+            entity_id: String::new(),
+            path: path.to_string(),
+        }
+    }
+}
+
+struct Failure {
+    path_prefix: String,
+    status: u16,
+    remaining: Option<usize>,
+}
+
+struct State {
+    mounts: HashMap<String, KvMount>,
+    transit: HashMap<String, TransitMount>,
+    databases: HashMap<String, DatabaseMount>,
+    pki: Vec<String>,
+    wrapped: HashMap<String, WrappedResponse>,
+    capabilities: HashMap<String, Vec<String>>,
+    sealed: bool,
+    standby: bool,
+    leases: BTreeMap<String, FakeLease>,
+    tokens: HashMap<String, FakeToken>,
+    github_tokens: HashMap<String, Vec<String>>,
+    app_roles: HashMap<(String, String), Vec<String>>,
+    failures: Vec<Failure>,
+    token_ttl: i64,
+    issued: usize,
+    requests: Vec<RecordedRequest>,
+}
+
+impl State {
+    fn new() -> State {
+        let mut tokens = HashMap::new();
+        tokens.insert(
+            ROOT_TOKEN.to_string(),
+            FakeToken::new("root", vec!["root".to_string()], "auth/token/root"),
+        );
+        State {
+            mounts: HashMap::new(),
+            transit: HashMap::new(),
+            databases: HashMap::new(),
+            pki: Vec::new(),
+            wrapped: HashMap::new(),
+            capabilities: HashMap::new(),
+            sealed: false,
+            standby: false,
+            leases: BTreeMap::new(),
+            tokens,
+            github_tokens: HashMap::new(),
+            app_roles: HashMap::new(),
+            failures: Vec::new(),
+            token_ttl: 3600,
+            issued: 0,
+            requests: Vec::new(),
+        }
+    }
+}
+
+/// A fake Vault server listening on a random local port.
+///
+/// The server is shut down when the `FakeVault` is dropped.
+pub struct FakeVault {
+    addr: Url,
+    socket_addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl FakeVault {
+    /// Start a server with no mounts and only the [`ROOT_TOKEN`](constant.ROOT_TOKEN.html).
+    pub fn start() -> FakeVault {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind fake vault");
+        let socket_addr = listener.local_addr().unwrap();
+        let addr = format!("http://{}/", socket_addr).parse().unwrap();
+        let state = Arc::new(Mutex::new(State::new()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let state = state.clone();
+            let shutdown = shutdown.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shutdown.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        let state = state.clone();
+                        thread::spawn(move || handle_connection(stream, &state));
+                    }
+                }
+            })
+        };
+        FakeVault {
+            addr,
+            socket_addr,
+            state,
+            shutdown,
+            handle: Some(handle),
+        }
+    }
+
+    /// Base URL of the server.
+    pub fn addr(&self) -> &Url {
+        &self.addr
+    }
+
+    /// A `VaultClient` pointed at this server using the root token.
+    pub fn client(&self) -> VaultClient {
+        VaultClient::from_client_token(self.addr.clone(), ROOT_TOKEN)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Mount a K/V version 1 engine at `mount`.
+    pub fn mount_kv1(&self, mount: &str) {
+        self.mount(mount, KvVersion::V1);
+    }
+
+    /// Mount a K/V version 2 engine at `mount`.
+    pub fn mount_kv2(&self, mount: &str) {
+        self.mount(mount, KvVersion::V2);
+    }
+
+    fn mount(&self, mount: &str, version: KvVersion) {
+        self.state().mounts.insert(
+            trim_slashes(mount).to_string(),
+            KvMount {
+                version,
+                secrets: BTreeMap::new(),
+            },
+        );
+    }
+
+    /// Mount a Transit engine at `mount`.  Keys are created on first use.
+    pub fn mount_transit(&self, mount: &str) {
+        self.state().transit.insert(
+            trim_slashes(mount).to_string(),
+            TransitMount {
+                keys: BTreeMap::new(),
+            },
+        );
+    }
+
+    /// Mount a database secrets engine at `mount`, with no roles.
+    pub fn mount_database(&self, mount: &str) {
+        self.state().databases.insert(
+            trim_slashes(mount).to_string(),
+            DatabaseMount {
+                roles: BTreeMap::new(),
+            },
+        );
+    }
+
+    /// Add a role to the database engine mounted at `mount`, whose credentials
+    /// (read from `<mount>/creds/<role>`) are leased for `ttl` seconds.
+    ///
+    /// Panics if no database engine is mounted at `mount`.
+    pub fn add_database_role(&self, mount: &str, role: &str, ttl: i64) {
+        self.state()
+            .databases
+            .get_mut(trim_slashes(mount))
+            .unwrap_or_else(|| panic!("No database engine mounted at {}", mount))
+            .roles
+            .insert(role.to_string(), ttl);
+    }
+
+    /// Mount a PKI engine at `mount`.  Any role can issue certificates.
+    pub fn mount_pki(&self, mount: &str) {
+        self.state().pki.push(trim_slashes(mount).to_string());
+    }
+
+    /// Wrap `data` as if it had been read with a wrap TTL of `ttl` seconds (ex. an App Role
+    /// `secret_id`), returning the wrapping token.
+    pub fn wrap(&self, data: &[(&str, &str)], ttl: i64) -> String {
+        let data: Map<String, Value> = data
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+            .collect();
+        let mut state = self.state();
+        let body = envelope(&state, Value::Object(data));
+        wrap_response(&mut state, "fake/wrap", ttl, body)
+    }
+
+    /// IDs of the leases that have been issued and not revoked.
+    pub fn leases(&self) -> Vec<String> {
+        self.state().leases.keys().cloned().collect()
+    }
+
+    /// Write a secret to the K/V engine mounted at `mount`, creating a new version.
+    ///
+    /// Panics if nothing is mounted at `mount`.
+    pub fn put_secret(&self, mount: &str, path: &str, data: &[(&str, &str)]) {
+        let data = data
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+            .collect();
+        let mut state = self.state();
+        let kv = state
+            .mounts
+            .get_mut(trim_slashes(mount))
+            .unwrap_or_else(|| panic!("Nothing mounted at {}", mount));
+        write_secret(kv, trim_slashes(path), data);
+    }
+
+    /// Accept `token` as a client token that never expires.
+    pub fn add_token(&self, token: &str) {
+        self.state().tokens.insert(
+            token.to_string(),
+            FakeToken::new("token", vec!["default".to_string()], "auth/token/create"),
+        );
+    }
+
+    /// Allow GitHub login with `github_token`, issuing tokens with `policies`.
+    pub fn add_github_token(&self, github_token: &str, policies: &[&str]) {
+        self.state()
+            .github_tokens
+            .insert(github_token.to_string(), to_strings(policies));
+    }
+
+    /// Allow App Role login with `role_id` and `secret_id`, issuing tokens with `policies`.
+    pub fn add_app_role(&self, role_id: &str, secret_id: &str, policies: &[&str]) {
+        self.state().app_roles.insert(
+            (role_id.to_string(), secret_id.to_string()),
+            to_strings(policies),
+        );
+    }
+
+    /// Set the TTL in seconds of tokens issued by login.  A TTL of `0` issues tokens
+    /// that never expire.
+    pub fn set_token_ttl(&self, seconds: i64) {
+        self.state().token_ttl = seconds;
+    }
+
+    /// Report `capabilities` for `path` from `sys/capabilities-self`, for any token.
+    /// Unconfigured paths report `root` for the root token, and `deny` otherwise.
+    pub fn set_capabilities(&self, path: &str, capabilities: &[&str]) {
+        self.state()
+            .capabilities
+            .insert(trim_slashes(path).to_string(), to_strings(capabilities));
+    }
+
+    /// Report the server as sealed from `sys/health` and `sys/seal-status`, and respond
+    /// `503 Service Unavailable` to every other request while sealed.
+    pub fn set_sealed(&self, sealed: bool) {
+        self.state().sealed = sealed;
+    }
+
+    /// Report the server as a standby node from `sys/health`.
+    pub fn set_standby(&self, standby: bool) {
+        self.state().standby = standby;
+    }
+
+    /// Respond with `status` to the next `count` requests whose path starts with `path_prefix`
+    /// (ex. `/v1/secret/data/`).
+    pub fn fail_requests(&self, path_prefix: &str, status: u16, count: usize) {
+        self.state().failures.push(Failure {
+            path_prefix: path_prefix.to_string(),
+            status,
+            remaining: Some(count),
+        });
+    }
+
+    /// Respond with `status` to every request whose path starts with `path_prefix`.
+    pub fn fail_all_requests(&self, path_prefix: &str, status: u16) {
+        self.state().failures.push(Failure {
+            path_prefix: path_prefix.to_string(),
+            status,
+            remaining: None,
+        });
+    }
+
+    /// Remove all injected failures.
+    pub fn clear_failures(&self) {
+        self.state().failures.clear();
+    }
+
+    /// All requests received so far, in order.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state().requests.clone()
+    }
+}
+
+impl Drop for FakeVault {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake the accept loop so it can observe the shutdown flag.
+        let _ = TcpStream::connect(self.socket_addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Option<String>,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Response {
+        Response { status: 200, body }
+    }
+
+    fn no_content() -> Response {
+        Response {
+            status: 204,
+            body: Value::Null,
+        }
+    }
+
+    fn error(status: u16, msg: &str) -> Response {
+        Response {
+            status,
+            body: json!({ "errors": [msg] }),
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, state: &Mutex<State>) {
+    let mut reader = BufReader::new(match stream.try_clone() {
+        Ok(s) => s,
+        Err(_) => return,
+    });
+    let mut stream = stream;
+    if let Some(req) = read_request(&mut reader) {
+        let resp = route(&req, &mut state.lock().unwrap());
+        let _ = write_response(&mut stream, &resp);
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> Option<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?;
+    let (path, query) = match target.find('?') {
+        Some(i) => (target[..i].to_string(), Some(target[i + 1..].to_string())),
+        None => (target.to_string(), None),
+    };
+    let mut headers = HashMap::new();
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).ok()?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(i) = header.find(':') {
+            headers.insert(
+                header[..i].trim().to_lowercase(),
+                header[i + 1..].trim().to_string(),
+            );
+        }
+    }
+    let len = headers
+        .get("content-length")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).ok()?;
+    Some(Request {
+        method,
+        path,
+        query,
+        headers,
+        body,
+    })
+}
+
+fn write_response<W: Write>(w: &mut W, resp: &Response) -> std::io::Result<()> {
+    let body = if resp.status == 204 {
+        String::new()
+    } else {
+        resp.body.to_string()
+    };
+    write!(
+        w,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        resp.status,
+        reason(resp.status),
+        body.len(),
+        body
+    )?;
+    w.flush()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        429 => "Too Many Requests",
+        473 => "Performance Standby",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+fn route(req: &Request, state: &mut State) -> Response {
+    let body = serde_json::from_slice(&req.body).ok();
+    let token = req.headers.get("x-vault-token").cloned();
+    state.requests.push(RecordedRequest {
+        method: req.method.clone(),
+        path: req.path.clone(),
+        token: token.clone(),
+        body: body.clone(),
+    });
+
+    if let Some(status) = injected_failure(state, &req.path) {
+        return Response::error(status, "injected failure");
+    }
+
+    let path = match req.path.strip_prefix("/v1/") {
+        Some(p) => trim_slashes(p).to_string(),
+        None => return Response::error(404, "unsupported path"),
+    };
+    let body = body.unwrap_or(Value::Null);
+    if state.sealed && path != "sys/health" && path != "sys/seal-status" {
+        return Response::error(503, "Vault is sealed");
+    }
+
+    match (req.method.as_str(), path.as_str()) {
+        ("POST", "auth/github/login") | ("PUT", "auth/github/login") => {
+            let policies = body["token"]
+                .as_str()
+                .and_then(|t| state.github_tokens.get(t))
+                .cloned();
+            login(state, policies, "github", "auth/github/login")
+        }
+        ("POST", "auth/approle/login") | ("PUT", "auth/approle/login") => {
+            let policies = match (body["role_id"].as_str(), body["secret_id"].as_str()) {
+                (Some(r), Some(s)) => state
+                    .app_roles
+                    .get(&(r.to_string(), s.to_string()))
+                    .cloned(),
+                _ => None,
+            };
+            login(state, policies, "approle", "auth/approle/login")
+        }
+        ("GET", "sys/health") => health(state, req.query.as_deref()),
+        ("GET", "sys/seal-status") => Response::ok(json!({
+            "type": "shamir",
+            "initialized": true,
+            "sealed": state.sealed,
+            "t": 3,
+            "n": 5,
+            "progress": 0,
+            "version": FAKE_VERSION,
+            "cluster_name": FAKE_CLUSTER_NAME,
+        })),
+        (method, p) if p.starts_with("sys/wrapping/") => {
+            let op = &p["sys/wrapping/".len()..];
+            wrapping(state, method, op, token.as_deref(), &body)
+        }
+        _ => {
+            if !token_is_valid(state, token.as_deref()) {
+                return Response::error(403, "permission denied");
+            }
+            let resp = dispatch(state, req, &path, body);
+            match req.headers.get("x-vault-wrap-ttl") {
+                Some(ttl) if resp.status == 200 => match parse_ttl(ttl) {
+                    Some(ttl) => {
+                        let token = wrap_response(state, &path, ttl, resp.body);
+                        let mut wrapped = envelope(state, Value::Null);
+                        wrapped["wrap_info"] = wrap_info(state, &token);
+                        Response::ok(wrapped)
+                    }
+                    None => Response::error(400, "invalid wrap TTL"),
+                },
+                _ => resp,
+            }
+        }
+    }
+}
+
+/// Route an authenticated request to a system backend or engine.
+fn dispatch(state: &mut State, req: &Request, path: &str, body: Value) -> Response {
+    let is_list =
+        req.method == "LIST" || (req.method == "GET" && req.query.as_deref() == Some("list=true"));
+    if path == "auth/token/lookup-self" {
+        let token = req.headers["x-vault-token"].as_str();
+        return lookup_self(state, token);
+    }
+    if let Some(mount) = path.strip_prefix("sys/internal/ui/mounts/") {
+        return mount_info(state, mount);
+    }
+    if path == "sys/capabilities-self" {
+        let token = req.headers["x-vault-token"].as_str();
+        return capabilities_self(state, token, &body);
+    }
+    if let Some(op) = path.strip_prefix("sys/leases/") {
+        return leases(state, &req.method, op, &body);
+    }
+    if let Some((mount, rest)) = find_mount(state.transit.keys(), path) {
+        let transit = state.transit.get_mut(&mount).unwrap();
+        let resp = transit_engine(transit, &req.method, &rest, is_list, &body);
+        return engine_response(state, resp);
+    }
+    if let Some((mount, rest)) = find_mount(state.pki.iter(), path) {
+        let resp = pki_engine(&mount, &req.method, &rest, &body);
+        return engine_response(state, resp);
+    }
+    if let Some((mount, rest)) = find_mount(state.databases.keys(), path) {
+        return database_engine(state, &mount, &req.method, &rest);
+    }
+    kv(state, &req.method, path, is_list, body)
+}
+
+/// Store a response `body` under a new wrapping token.
+fn wrap_response(state: &mut State, creation_path: &str, ttl: i64, body: Value) -> String {
+    state.issued += 1;
+    let token = format!("s.fake-wrapping-token-{}", state.issued);
+    state.wrapped.insert(
+        token.clone(),
+        WrappedResponse {
+            body,
+            creation_path: creation_path.to_string(),
+            ttl,
+        },
+    );
+    token
+}
+
+fn wrap_info(state: &State, token: &str) -> Value {
+    let wrapped = &state.wrapped[token];
+    json!({
+        "token": token,
+        "accessor": format!("fake-wrapping-accessor-{}", state.issued),
+        "ttl": wrapped.ttl,
+        "creation_time": "2019-01-01T00:00:00.000000Z",
+        "creation_path": wrapped.creation_path,
+        "wrapped_accessor": "",
+    })
+}
+
+/// Parse a TTL of seconds, optionally suffixed with `s`, `m` or `h`.
+fn parse_ttl(ttl: &str) -> Option<i64> {
+    let (n, unit) = match ttl.chars().last()? {
+        's' => (&ttl[..ttl.len() - 1], 1),
+        'm' => (&ttl[..ttl.len() - 1], 60),
+        'h' => (&ttl[..ttl.len() - 1], 3600),
+        _ => (ttl, 1),
+    };
+    n.parse::<i64>().ok().map(|n| n * unit)
+}
+
+/// `sys/wrapping/*`.  The wrapping token is given in the body by an authenticated client,
+/// or as the request's token when unwrapping without a client token.
+fn wrapping(
+    state: &mut State,
+    method: &str,
+    op: &str,
+    token: Option<&str>,
+    body: &Value,
+) -> Response {
+    if method != "POST" && method != "PUT" {
+        return Response::error(405, "unsupported operation");
+    }
+    let wrapping_token = match (body["token"].as_str(), op) {
+        (Some(t), "lookup") => t.to_string(),
+        (Some(t), _) if token_is_valid(state, token) => t.to_string(),
+        (Some(_), _) => return Response::error(403, "permission denied"),
+        (None, _) => token.unwrap_or("").to_string(),
+    };
+    if !state.wrapped.contains_key(&wrapping_token) {
+        return Response::error(400, "wrapping token is not valid or does not exist");
+    }
+    match op {
+        "unwrap" => Response::ok(state.wrapped.remove(&wrapping_token).unwrap().body),
+        "lookup" => {
+            let wrapped = &state.wrapped[&wrapping_token];
+            let data = json!({
+                "creation_path": wrapped.creation_path,
+                "creation_time": "2019-01-01T00:00:00.000000Z",
+                "creation_ttl": wrapped.ttl,
+            });
+            Response::ok(envelope(state, data))
+        }
+        "rewrap" => {
+            let wrapped = state.wrapped.remove(&wrapping_token).unwrap();
+            let token = wrap_response(state, &wrapped.creation_path, wrapped.ttl, wrapped.body);
+            let mut resp = envelope(state, Value::Null);
+            resp["wrap_info"] = wrap_info(state, &token);
+            Response::ok(resp)
+        }
+        _ => Response::error(404, "no handler for route"),
+    }
+}
+
+fn injected_failure(state: &mut State, path: &str) -> Option<u16> {
+    let failure = state
+        .failures
+        .iter_mut()
+        .find(|f| path.starts_with(&f.path_prefix) && f.remaining != Some(0))?;
+    if let Some(n) = failure.remaining.as_mut() {
+        *n -= 1;
+    }
+    Some(failure.status)
+}
+
+fn token_is_valid(state: &State, token: Option<&str>) -> bool {
+    match token.and_then(|t| state.tokens.get(t)) {
+        Some(FakeToken {
+            expires: Some(expires),
+            ..
+        }) => *expires > Instant::now(),
+        Some(_) => true,
+        None => false,
+    }
+}
+
+/// `sys/health`, with status codes overridable by query parameters like Vault's.
+fn health(state: &State, query: Option<&str>) -> Response {
+    let param = |name: &str| {
+        query?
+            .split('&')
+            .filter_map(|kv| kv.split_once('='))
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| v.to_string())
+    };
+    let status = if state.sealed {
+        param("sealedcode")
+            .and_then(|c| c.parse().ok())
+            .unwrap_or(503)
+    } else if state.standby && param("standbyok").as_deref() != Some("true") {
+        param("standbycode")
+            .and_then(|c| c.parse().ok())
+            .unwrap_or(429)
+    } else {
+        200
+    };
+    Response {
+        status,
+        body: json!({
+            "initialized": true,
+            "sealed": state.sealed,
+            "standby": state.standby,
+            "performance_standby": false,
+            "server_time_utc": 1546300800,
+            "version": FAKE_VERSION,
+            "cluster_name": FAKE_CLUSTER_NAME,
+        }),
+    }
+}
+
+fn mount_info(state: &State, mount: &str) -> Response {
+    let mount = trim_slashes(mount);
+    let (mount_type, options) = if let Some(kv) = state.mounts.get(mount) {
+        let version = match kv.version {
+            KvVersion::V1 => "1",
+            KvVersion::V2 => "2",
+        };
+        ("kv", json!({ "version": version }))
+    } else if state.transit.contains_key(mount) {
+        ("transit", Value::Null)
+    } else if state.databases.contains_key(mount) {
+        ("database", Value::Null)
+    } else if state.pki.iter().any(|p| p == mount) {
+        ("pki", Value::Null)
+    } else {
+        return Response::error(404, &format!("no mount found at {}", mount));
+    };
+    let data = json!({
+        "type": mount_type,
+        "path": format!("{}/", mount),
+        "options": options,
+    });
+    Response::ok(envelope(state, data))
+}
+
+fn capabilities_self(state: &State, token: &str, body: &Value) -> Response {
+    let is_root = state.tokens[token].policies.iter().any(|p| p == "root");
+    let paths = body["paths"].as_array().cloned().unwrap_or_default();
+    let mut data = Map::new();
+    for path in paths.iter().filter_map(Value::as_str) {
+        let caps = match state.capabilities.get(trim_slashes(path)) {
+            Some(caps) => caps.clone(),
+            None if is_root => vec!["root".to_string()],
+            None => vec!["deny".to_string()],
+        };
+        data.insert(path.to_string(), json!(caps));
+    }
+    Response::ok(envelope(state, Value::Object(data)))
+}
+
+fn lookup_self(state: &State, token: &str) -> Response {
+    let t = &state.tokens[token];
+    let ttl = match t.expires {
+        Some(expires) => expires
+            .checked_duration_since(Instant::now())
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        None => 0,
+    };
+    let data = json!({
+        "accessor": format!("fake-accessor-{}", token),
+        "creation_time": 1546300800,
+        "creation_ttl": t.ttl,
+        "display_name": t.display_name,
+        "entity_id": t.entity_id,
+        "expire_time": if t.expires.is_some() { json!("2019-01-01T01:00:00.000000Z") } else { Value::Null },
+        "explicit_max_ttl": 0,
+        "id": token,
+        "meta": null,
+        "num_uses": 0,
+        "orphan": true,
+        "path": t.path,
+        "policies": t.policies,
+        "renewable": t.expires.is_some(),
+        "ttl": ttl,
+        "type": "service",
+    });
+    Response::ok(envelope(state, data))
+}
+
+fn login(state: &mut State, policies: Option<Vec<String>>, method: &str, path: &str) -> Response {
+    let policies = match policies {
+        Some(p) => p,
+        None => return Response::error(400, "invalid credentials"),
+    };
+    state.issued += 1;
+    let token = format!("s.fake-token-{}", state.issued);
+    let ttl = state.token_ttl;
+    let expires = if ttl > 0 {
+        Some(Instant::now() + Duration::from_secs(ttl as u64))
+    } else {
+        None
+    };
+    state.tokens.insert(
+        token.clone(),
+        FakeToken {
+            expires,
+            ttl,
+            policies: policies.clone(),
+            display_name: format!("{}-fake-user", method),
+            entity_id: format!("fake-entity-{}", state.issued),
+            path: path.to_string(),
+        },
+    );
+    let mut resp = envelope(state, Value::Null);
+    resp["auth"] = json!({
+        "client_token": token,
+        "accessor": format!("fake-accessor-{}", state.issued),
+        "policies": policies,
+        "token_policies": policies,
+        "metadata": {},
+        "lease_duration": ttl,
+        "renewable": ttl > 0,
+        "entity_id": format!("fake-entity-{}", state.issued),
+        "token_type": "service",
+        "orphan": true,
+    });
+    Response::ok(resp)
+}
+
+/// Engine handlers return the `data` of a successful response, or a complete error response.
+fn engine_response(state: &State, result: Result<Value, Response>) -> Response {
+    match result {
+        Ok(data) => Response::ok(envelope(state, data)),
+        Err(resp) => resp,
+    }
+}
+
+/// The longest mount in `mounts` that `path` is under, and the rest of the path.
+fn find_mount<'a, I>(mounts: I, path: &str) -> Option<(String, String)>
+where
+    I: Iterator<Item = &'a String>,
+{
+    let mount = mounts
+        .filter(|m| path == m.as_str() || path.starts_with(&format!("{}/", m)))
+        .max_by_key(|m| m.len())?;
+    let rest = trim_slashes(&path[mount.len()..]).to_string();
+    Some((mount.clone(), rest))
+}
+
+fn transit_engine(
+    transit: &mut TransitMount,
+    method: &str,
+    path: &str,
+    is_list: bool,
+    body: &Value,
+) -> Result<Value, Response> {
+    if is_list && path == "keys" {
+        let keys: Vec<&String> = transit.keys.keys().collect();
+        return if keys.is_empty() {
+            Err(Response::error(404, ""))
+        } else {
+            Ok(json!({ "keys": keys }))
+        };
+    }
+    if method != "POST" && method != "PUT" {
+        return Err(Response::error(405, "unsupported operation"));
+    }
+    let mut split = path.splitn(2, '/');
+    let (op, key) = (split.next().unwrap_or(""), split.next().unwrap_or(""));
+    let invalid = |msg: &str| Response::error(400, msg);
+    match op {
+        "encrypt" => {
+            let version = *transit.keys.entry(key.to_string()).or_insert(1);
+            let plaintext = body["plaintext"]
+                .as_str()
+                .ok_or_else(|| invalid("missing plaintext"))?;
+            Ok(json!({ "ciphertext": fake_encrypt(version, plaintext) }))
+        }
+        "decrypt" | "rewrap" => {
+            let version = *transit
+                .keys
+                .get(key)
+                .ok_or_else(|| invalid("encryption key not found"))?;
+            let plaintext = body["ciphertext"]
+                .as_str()
+                .and_then(fake_decrypt)
+                .ok_or_else(|| invalid("invalid ciphertext"))?;
+            if op == "decrypt" {
+                Ok(json!({ "plaintext": plaintext }))
+            } else {
+                Ok(json!({ "ciphertext": fake_encrypt(version, &plaintext) }))
+            }
+        }
+        "keys" => {
+            let mut split = key.splitn(2, '/');
+            let (key, action) = (split.next().unwrap_or(""), split.next());
+            match action {
+                None => {
+                    transit.keys.entry(key.to_string()).or_insert(1);
+                }
+                Some("rotate") => {
+                    let version = transit
+                        .keys
+                        .get_mut(key)
+                        .ok_or_else(|| invalid("encryption key not found"))?;
+                    *version += 1;
+                }
+                Some(_) => return Err(Response::error(404, "no handler for route")),
+            }
+            Err(Response::no_content())
+        }
+        _ => Err(Response::error(404, "no handler for route")),
+    }
+}
+
+fn database_engine(state: &mut State, mount: &str, method: &str, path: &str) -> Response {
+    let role = match path.strip_prefix("creds/") {
+        Some(r) => r,
+        None => return Response::error(404, "no handler for route"),
+    };
+    if method != "GET" {
+        return Response::error(405, "unsupported operation");
+    }
+    let ttl = match state.databases[mount].roles.get(role) {
+        Some(ttl) => *ttl,
+        None => return Response::error(400, &format!("unknown role: {}", role)),
+    };
+    state.issued += 1;
+    let lease_id = format!("{}/creds/{}/fake-lease-{}", mount, role, state.issued);
+    state.leases.insert(
+        lease_id.clone(),
+        FakeLease {
+            ttl,
+            expires: Instant::now() + Duration::from_secs(ttl as u64),
+        },
+    );
+    let mut resp = envelope(
+        state,
+        json!({
+            "username": format!("v-{}-{}", role, state.issued),
+            "password": format!("fake-password-{}", state.issued),
+        }),
+    );
+    resp["lease_id"] = json!(lease_id);
+    resp["lease_duration"] = json!(ttl);
+    resp["renewable"] = json!(true);
+    Response::ok(resp)
+}
+
+fn pki_engine(mount: &str, method: &str, path: &str, body: &Value) -> Result<Value, Response> {
+    if path.strip_prefix("issue/").is_none() {
+        return Err(Response::error(404, "no handler for route"));
+    }
+    if method != "POST" && method != "PUT" {
+        return Err(Response::error(405, "unsupported operation"));
+    }
+    let common_name = body["common_name"]
+        .as_str()
+        .ok_or_else(|| Response::error(400, "the common_name field is required"))?;
+    let issuing_ca = fake_pem("CERTIFICATE", &format!("{} intermediate CA", mount));
+    Ok(json!({
+        "certificate": fake_pem("CERTIFICATE", common_name),
+        "issuing_ca": issuing_ca,
+        "ca_chain": [issuing_ca, fake_pem("CERTIFICATE", &format!("{} root CA", mount))],
+        "private_key": fake_pem("RSA PRIVATE KEY", common_name),
+        "private_key_type": "rsa",
+        "serial_number": "39:dd:2e:90:b7:23:1f:8d:d3:7d:31:c5:1b:da:84:d0:5b:65:31:58",
+        "expiration": 1577836800,
+    }))
+}
+
+/// A PEM block whose content is `subject`, base64 encoded.
+fn fake_pem(label: &str, subject: &str) -> String {
+    format!(
+        "-----BEGIN {}-----\n{}\n-----END {}-----",
+        label,
+        base64::encode(subject),
+        label
+    )
+}
+
+fn leases(state: &mut State, method: &str, op: &str, body: &Value) -> Response {
+    if method != "POST" && method != "PUT" {
+        return Response::error(405, "unsupported operation");
+    }
+    let lease_id = match body["lease_id"].as_str() {
+        Some(id) => id.to_string(),
+        None => return Response::error(400, "missing lease_id"),
+    };
+    let now = Instant::now();
+    let lease = match state.leases.get_mut(&lease_id) {
+        Some(l) if l.expires > now => l,
+        _ => return Response::error(400, "invalid lease"),
+    };
+    match op {
+        "renew" => {
+            let ttl = body["increment"].as_i64().unwrap_or(lease.ttl);
+            lease.expires = now + Duration::from_secs(ttl as u64);
+            let mut resp = envelope(state, Value::Null);
+            resp["lease_id"] = json!(lease_id);
+            resp["lease_duration"] = json!(ttl);
+            resp["renewable"] = json!(true);
+            Response::ok(resp)
+        }
+        "revoke" => {
+            state.leases.remove(&lease_id);
+            Response::no_content()
+        }
+        "lookup" => {
+            let ttl = lease.expires.duration_since(now).as_secs();
+            let data = json!({
+                "id": lease_id,
+                "issue_time": "2019-01-01T00:00:00.000000Z",
+                "expire_time": "2019-01-01T01:00:00.000000Z",
+                "last_renewal": null,
+                "renewable": true,
+                "ttl": ttl,
+            });
+            Response::ok(envelope(state, data))
+        }
+        _ => Response::error(404, "no handler for route"),
+    }
+}
+
+/// Fake ciphertext that looks like Vault's, but is only base64 encoded.
+fn fake_encrypt(version: i64, plaintext_b64: &str) -> String {
+    format!("vault:v{}:{}", version, base64::encode(plaintext_b64))
+}
+
+fn fake_decrypt(ciphertext: &str) -> Option<String> {
+    let rest = ciphertext.strip_prefix("vault:v")?;
+    let encoded = &rest[rest.find(':')? + 1..];
+    String::from_utf8(base64::decode(encoded).ok()?).ok()
+}
+
+fn kv(state: &mut State, method: &str, path: &str, is_list: bool, body: Value) -> Response {
+    let (mount_name, rest) = match find_mount(state.mounts.keys(), path) {
+        Some(m) => m,
+        None => return Response::error(404, "no handler for route"),
+    };
+    let kv = state.mounts.get_mut(&mount_name).unwrap();
+    let (secret_path, is_metadata) = match kv.version {
+        KvVersion::V1 => (rest, false),
+        KvVersion::V2 => {
+            let mut split = rest.splitn(2, '/');
+            let prefix = split.next().unwrap_or("");
+            let secret_path = split.next().unwrap_or("").to_string();
+            match prefix {
+                "data" => (secret_path, false),
+                "metadata" => (secret_path, true),
+                _ => return Response::error(404, "no handler for route"),
+            }
+        }
+    };
+
+    if is_list {
+        if kv.version == KvVersion::V2 && !is_metadata {
+            return Response::error(405, "unsupported operation");
+        }
+        let keys = list_keys(kv, &secret_path);
+        return if keys.is_empty() {
+            Response::error(404, "")
+        } else {
+            let data = json!({ "keys": keys });
+            Response::ok(envelope(state, data))
+        };
+    }
+
+    match method {
+        "GET" => {
+            let data = match kv.secrets.get(&secret_path) {
+                Some(s) if kv.version == KvVersion::V2 && is_metadata => json!({
+                    "current_version": s.version,
+                    "created_time": "2019-01-01T00:00:00.000000Z",
+                    "updated_time": "2019-01-01T00:00:00.000000Z",
+                }),
+                Some(s) if kv.version == KvVersion::V2 => json!({
+                    "data": s.data,
+                    "metadata": kv_metadata(s.version),
+                }),
+                Some(s) => Value::Object(s.data.clone()),
+                None => return Response::error(404, ""),
+            };
+            Response::ok(envelope(state, data))
+        }
+        "POST" | "PUT" if !is_metadata => {
+            let data = match kv.version {
+                KvVersion::V1 => body.as_object().cloned(),
+                KvVersion::V2 => body["data"].as_object().cloned(),
+            };
+            let data = match data {
+                Some(d) => d,
+                None => return Response::error(400, "no data provided"),
+            };
+            let version = write_secret(kv, &secret_path, data);
+            match kv.version {
+                KvVersion::V1 => Response::no_content(),
+                KvVersion::V2 => Response::ok(envelope(state, kv_metadata(version))),
+            }
+        }
+        "DELETE" => {
+            kv.secrets.remove(&secret_path);
+            Response::no_content()
+        }
+        _ => Response::error(405, "unsupported operation"),
+    }
+}
+
+fn write_secret(kv: &mut KvMount, path: &str, data: Map<String, Value>) -> i64 {
+    let version = kv.secrets.get(path).map(|s| s.version + 1).unwrap_or(1);
+    kv.secrets
+        .insert(path.to_string(), KvSecret { data, version });
+    version
+}
+
+fn list_keys(kv: &KvMount, dir: &str) -> Vec<String> {
+    let prefix = if dir.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", dir)
+    };
+    let mut keys: Vec<String> = kv
+        .secrets
+        .keys()
+        .filter_map(|k| k.strip_prefix(&prefix))
+        .map(|rest| match rest.find('/') {
+            Some(i) => rest[..=i].to_string(),
+            None => rest.to_string(),
+        })
+        .collect();
+    keys.dedup();
+    keys
+}
+
+fn kv_metadata(version: i64) -> Value {
+    json!({
+        "created_time": "2019-01-01T00:00:00.000000Z",
+        "deletion_time": "",
+        "destroyed": false,
+        "version": version,
+    })
+}
+
+fn envelope(state: &State, data: Value) -> Value {
+    json!({
+        "request_id": format!("fake-request-{}", state.requests.len()),
+        "lease_id": "",
+        "renewable": false,
+        "lease_duration": 0,
+        "data": data,
+        "wrap_info": null,
+        "warnings": null,
+        "auth": null,
+    })
+}
+
+fn trim_slashes(s: &str) -> &str {
+    s.trim_matches('/')
+}
+
+fn to_strings(s: &[&str]) -> Vec<String> {
+    s.iter().map(|s| s.to_string()).collect()
+}